@@ -0,0 +1,445 @@
+//! What a binding does once it fires.
+//!
+//! In the config a binding either has a `command = [...]`, short for a `spawn`
+//! action, or an `action` table whose `type` picks one of the registered [`Actions`]:
+//!
+//! ```toml
+//! [[binding]]
+//! button = "BTN_MODE"
+//! action = { type = "shell", command = "notify-send \"$GUIDERS_DEVICE_NAME\"" }
+//! ```
+//!
+//! Every command gets the `GUIDERS_*` variables from [`Context::env`], and the
+//! arguments of `spawn` and the `fifo` message can use the placeholders of
+//! [`Context::expand`], like `command = ["notify-send", "{device}: {button}"]`.
+//!
+//! A `keys` action types on a virtual keyboard instead of running anything. Its steps
+//! are key combinations, pressed in order and let go in reverse, or pauses in milliseconds,
+//! at most [`MAX_PAUSE_MS`] in all:
+//!
+//! ```toml
+//! [[binding]]
+//! button = "BTN_MODE"
+//! action = { type = "keys", keys = ["KEY_LEFTCTRL+KEY_LEFTALT+KEY_T"] }
+//!
+//! [[binding]]
+//! button = "BTN_SELECT"
+//! action = { type = "keys", keys = ["KEY_LEFTMETA", 200, "KEY_F", "KEY_O", "KEY_O"] }
+//! ```
+
+use evdev::{InputEvent, KeyCode, KeyEvent};
+use std::{
+    collections::HashMap, fs::OpenOptions, io::Write, os::unix::fs::OpenOptionsExt, path::PathBuf,
+    process::Command, thread, time::Duration,
+};
+use toml::{Table, Value};
+
+use crate::{Binding, Button, ButtonEvent, Errors, supervisor::Supervisor, uinput::Keyboard};
+
+/// Something a binding can do, run from the listener thread.
+pub trait Action {
+    fn run(&self, ctx: &mut Context<'_>) -> Result<(), Errors>;
+}
+
+pub(crate) type EmitHandler = Box<dyn FnMut(&str, &ButtonEvent)>;
+
+/// What an [`Action`] gets to work with.
+pub struct Context<'a> {
+    pub event: &'a ButtonEvent,
+    pub binding: &'a Binding,
+    pub supervisor: &'a mut Supervisor,
+    /// What the supervisor files the binding's children under, kept across reloads.
+    pub(crate) run_id: usize,
+    pub(crate) emit: &'a mut Vec<EmitHandler>,
+}
+
+impl Context<'_> {
+    /// Starts `command` under the supervisor, following the binding's `concurrency`,
+    /// with the variables from [`Context::env`] set.
+    pub fn spawn(&mut self, mut command: Command) -> Result<(), Errors> {
+        command.envs(self.env());
+        self.supervisor.spawn(self.run_id, self.binding, command)
+    }
+
+    /// Describes the event for the command that handles it.
+    pub fn env(&self) -> Vec<(&'static str, String)> {
+        let mut env: Vec<(&'static str, String)> = self
+            .fields()
+            .into_iter()
+            .map(|(var, _, value)| (var, value))
+            .collect();
+        env.push(("GUIDERS_BINDING", self.event.binding.to_string()));
+        env
+    }
+
+    /// Fills in `{device}`, `{devnode}`, `{button}`, `{kind}`, `{vendor}`, `{product}`
+    /// and `{uniq}`, other text is left alone.
+    pub fn expand(&self, arg: &str) -> String {
+        let placeholders = self.fields();
+        let mut expanded = String::with_capacity(arg.len());
+        let mut rest = arg;
+        'outer: while let Some(start) = rest.find('{') {
+            expanded.push_str(&rest[..start]);
+            rest = &rest[start..];
+            for (_, placeholder, value) in &placeholders {
+                if let Some(after) = rest.strip_prefix(placeholder) {
+                    expanded.push_str(value);
+                    rest = after;
+                    continue 'outer;
+                }
+            }
+            expanded.push('{');
+            rest = &rest[1..];
+        }
+        expanded.push_str(rest);
+        expanded
+    }
+
+    /// Environment variable, placeholder and value of everything known about the event.
+    fn fields(&self) -> [(&'static str, &'static str, String); 7] {
+        let controller = &self.event.controller;
+        [
+            ("GUIDERS_DEVICE_NAME", "{device}", controller.name.clone()),
+            ("GUIDERS_DEVNODE", "{devnode}", controller.devnode.clone()),
+            ("GUIDERS_BUTTON", "{button}", self.event.input()),
+            ("GUIDERS_PRESS_KIND", "{kind}", self.event.kind.to_string()),
+            (
+                "GUIDERS_VENDOR_ID",
+                "{vendor}",
+                format!("{:04x}", controller.vendor_id),
+            ),
+            (
+                "GUIDERS_PRODUCT_ID",
+                "{product}",
+                format!("{:04x}", controller.product_id),
+            ),
+            ("GUIDERS_UNIQ", "{uniq}", controller.uniq.clone()),
+        ]
+    }
+
+    /// Hands `name` and the event to the [`Listener::on_emit`](crate::Listener::on_emit) handlers.
+    pub fn emit(&mut self, name: &str) {
+        for handler in self.emit.iter_mut() {
+            handler(name, self.event);
+        }
+    }
+}
+
+/// Builds an action from the options of its `action` table in the config.
+pub type ActionFactory = Box<dyn Fn(&Table) -> Result<Box<dyn Action>, String>>;
+
+/// The action types a config can refer to by name.
+pub struct Actions {
+    factories: HashMap<String, ActionFactory>,
+    /// Shared by the `keys` actions.
+    keyboard: Keyboard,
+}
+
+impl Default for Actions {
+    /// The built-in `spawn`, `shell`, `fifo`, `emit` and `keys` actions.
+    fn default() -> Self {
+        let mut actions = Actions {
+            factories: HashMap::new(),
+            keyboard: Keyboard::default(),
+        };
+        actions.register("spawn", |options| {
+            Ok(Box::new(Spawn {
+                command: string_list(options, "command")?,
+            }))
+        });
+        actions.register("shell", |options| {
+            Ok(Box::new(Shell {
+                command: string(options, "command")?,
+            }))
+        });
+        actions.register("fifo", |options| {
+            Ok(Box::new(Fifo {
+                path: PathBuf::from(string(options, "path")?),
+                message: string(options, "message").ok(),
+            }))
+        });
+        actions.register("emit", |options| {
+            Ok(Box::new(Emit {
+                name: string(options, "name")?,
+            }))
+        });
+        let keyboard = actions.keyboard.clone();
+        actions.register("keys", move |options| {
+            let steps = key_steps(options)?;
+            keyboard.want();
+            Ok(Box::new(Keys {
+                steps,
+                keyboard: keyboard.clone(),
+            }))
+        });
+        actions
+    }
+}
+
+impl Actions {
+    /// Makes `name` usable as `action = { type = "<name>", ... }`, replacing any action of that name.
+    pub fn register(
+        &mut self,
+        name: &str,
+        factory: impl Fn(&Table) -> Result<Box<dyn Action>, String> + 'static,
+    ) {
+        self.factories.insert(name.to_string(), Box::new(factory));
+    }
+
+    /// The action configured for `binding`, `None` if it has neither a command nor an action.
+    pub fn build(&self, binding: &Binding) -> Result<Option<Box<dyn Action>>, Errors> {
+        let Some(options) = binding.action_options() else {
+            return Ok(None);
+        };
+        let invalid = |e: String| Errors::InvalidAction(binding.input(), e);
+
+        let kind = options
+            .get("type")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| invalid("action has no 'type'".to_string()))?;
+        let factory = self
+            .factories
+            .get(kind)
+            .ok_or_else(|| invalid(format!("unknown action type '{kind}'")))?;
+        factory(&options).map(Some).map_err(invalid)
+    }
+
+    /// Creates the virtual devices that the built actions need, like the keyboard of
+    /// `keys`. Building doesn't, so checking a config has no side effects.
+    pub(crate) fn open_devices(&self) -> Result<(), Errors> {
+        self.keyboard.open().map_err(|e| {
+            Errors::Action(format!(
+                "Failed to create the virtual keyboard through /dev/uinput: {e}"
+            ))
+        })
+    }
+}
+
+/// Runs `command[0]` with the rest as its arguments.
+pub struct Spawn {
+    pub command: Vec<String>,
+}
+
+impl Action for Spawn {
+    fn run(&self, ctx: &mut Context<'_>) -> Result<(), Errors> {
+        let mut command = Command::new(ctx.expand(&self.command[0]));
+        command.args(self.command[1..].iter().map(|arg| ctx.expand(arg)));
+        ctx.spawn(command)
+    }
+}
+
+/// Runs a command line through `sh -c`.
+///
+/// Placeholders aren't expanded here, device names could break the quoting;
+/// use the `GUIDERS_*` variables instead.
+pub struct Shell {
+    pub command: String,
+}
+
+impl Action for Shell {
+    fn run(&self, ctx: &mut Context<'_>) -> Result<(), Errors> {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(&self.command);
+        ctx.spawn(command)
+    }
+}
+
+/// Writes a line to a named pipe, by default the button and how it was pressed.
+pub struct Fifo {
+    pub path: PathBuf,
+    pub message: Option<String>,
+}
+
+impl Action for Fifo {
+    fn run(&self, ctx: &mut Context<'_>) -> Result<(), Errors> {
+        let message = match &self.message {
+            Some(message) => ctx.expand(message),
+            None => format!("{} {}", ctx.event.input(), ctx.event.kind),
+        };
+        // Nonblocking, so a FIFO nobody reads fails instead of hanging the listener.
+        OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&self.path)
+            .and_then(|mut fifo| writeln!(fifo, "{message}"))
+            .map_err(|e| Errors::Action(format!("Error writing to '{}': {e}", self.path.display())))
+    }
+}
+
+/// Passes the event to the listener's `on_emit` handlers under `name`.
+pub struct Emit {
+    pub name: String,
+}
+
+impl Action for Emit {
+    fn run(&self, ctx: &mut Context<'_>) -> Result<(), Errors> {
+        ctx.emit(&self.name);
+        Ok(())
+    }
+}
+
+/// Longest the pauses of a [`Keys`] action may add up to.
+pub const MAX_PAUSE_MS: u64 = 1000;
+
+/// One step of a [`Keys`] action.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyStep {
+    /// Keys pressed in this order, then let go in reverse.
+    Combo(Vec<KeyCode>),
+    Pause(Duration),
+}
+
+/// Types on guiders' virtual keyboard.
+///
+/// The listener waits for the pauses, so they're capped at [`MAX_PAUSE_MS`] in all.
+pub struct Keys {
+    pub steps: Vec<KeyStep>,
+    keyboard: Keyboard,
+}
+
+impl Action for Keys {
+    fn run(&self, _: &mut Context<'_>) -> Result<(), Errors> {
+        for step in &self.steps {
+            match step {
+                KeyStep::Combo(keys) => {
+                    let events: Vec<InputEvent> = keys
+                        .iter()
+                        .map(|&key| *KeyEvent::new(key, 1))
+                        .chain(keys.iter().rev().map(|&key| *KeyEvent::new(key, 0)))
+                        .collect();
+                    self.keyboard.emit(&events).map_err(|e| {
+                        Errors::Action(format!("Error typing on the virtual keyboard: {e}"))
+                    })?;
+                }
+                KeyStep::Pause(pause) => thread::sleep(*pause),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The `keys` list: combinations like `"KEY_LEFTCTRL+KEY_T"` and pauses in milliseconds.
+fn key_steps(options: &Table) -> Result<Vec<KeyStep>, String> {
+    let steps = options
+        .get("keys")
+        .and_then(|v| v.as_array())
+        .filter(|steps| !steps.is_empty())
+        .ok_or_else(|| "action needs a non-empty 'keys' list".to_string())?;
+    let steps = steps
+        .iter()
+        .map(|step| match step {
+            Value::String(combo) => combo
+                .split('+')
+                .map(|name| {
+                    let Button(key) = name.trim().parse()?;
+                    if Keyboard::supports(key) {
+                        Ok(key)
+                    } else {
+                        Err(format!("'{name}' isn't a keyboard key"))
+                    }
+                })
+                .collect::<Result<_, String>>()
+                .map(KeyStep::Combo),
+            Value::Integer(ms) if *ms >= 0 => Ok(KeyStep::Pause(Duration::from_millis(*ms as u64))),
+            _ => Err(format!(
+                "'keys' takes key combinations like \"KEY_LEFTCTRL+KEY_T\" and pauses in milliseconds, not {step}"
+            )),
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let paused: Duration = steps
+        .iter()
+        .filter_map(|step| match step {
+            KeyStep::Pause(pause) => Some(*pause),
+            KeyStep::Combo(_) => None,
+        })
+        .sum();
+    if paused > Duration::from_millis(MAX_PAUSE_MS) {
+        return Err(format!(
+            "pauses in 'keys' block the listener, so they can't add up to more than {MAX_PAUSE_MS} ms"
+        ));
+    }
+    Ok(steps)
+}
+
+fn string(options: &Table, key: &str) -> Result<String, String> {
+    options
+        .get(key)
+        .and_then(|v| v.as_str())
+        .map(str::to_string)
+        .ok_or_else(|| format!("action needs a '{key}' string"))
+}
+
+fn string_list(options: &Table, key: &str) -> Result<Vec<String>, String> {
+    let list: Vec<String> = options
+        .get(key)
+        .and_then(|v| v.as_array())
+        .map(|v| {
+            v.iter()
+                .filter_map(|v| v.as_str())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+    if list.is_empty() {
+        return Err(format!("action needs a non-empty '{key}' list"));
+    }
+    Ok(list)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Controller, Trigger, listener::PressKind};
+    use evdev::BusType;
+    use std::{path::PathBuf, sync::Arc, time::SystemTime};
+
+    fn expand(arg: &str) -> String {
+        let controller = Controller {
+            devnode: "/dev/input/event7".to_string(),
+            syspath: PathBuf::new(),
+            name: "Pad {x}".to_string(),
+            vendor_id: 0x045e,
+            product_id: 0x0b13,
+            version: 0,
+            bus_type: BusType::BUS_USB,
+            uniq: String::new(),
+            phys: String::new(),
+        };
+        let binding = Binding::new(Button(KeyCode::BTN_MODE), Trigger::Release);
+        let event = ButtonEvent {
+            controller: Arc::new(controller),
+            binding: 0,
+            button: binding.button.clone(),
+            axis: None,
+            kind: PressKind::Release,
+            time: SystemTime::UNIX_EPOCH,
+        };
+        let mut supervisor = Supervisor::default();
+        let ctx = Context {
+            event: &event,
+            binding: &binding,
+            supervisor: &mut supervisor,
+            run_id: 0,
+            emit: &mut Vec::new(),
+        };
+        ctx.expand(arg)
+    }
+
+    #[test]
+    fn fills_in_placeholders() {
+        assert_eq!(expand("{device}: {button}"), "Pad {x}: BTN_MODE");
+        assert_eq!(expand("{vendor}:{product}"), "045e:0b13");
+        assert_eq!(expand("{kind}{devnode}"), "release/dev/input/event7");
+        assert_eq!(expand("[{uniq}]"), "[]");
+    }
+
+    #[test]
+    fn leaves_other_braces_alone() {
+        assert_eq!(expand("{x} {"), "{x} {");
+        assert_eq!(expand("{{button}}"), "{BTN_MODE}");
+        assert_eq!(expand("{button"), "{button");
+        assert_eq!(expand("}{"), "}{");
+        assert_eq!(expand("no placeholders"), "no placeholders");
+    }
+}
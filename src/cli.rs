@@ -0,0 +1,308 @@
+//! Command line of the `guiders` binary.
+
+use guiders::{Errors, control::Request};
+use log::LevelFilter;
+use std::{iter, path::PathBuf};
+
+pub const USAGE: &str = "\
+Usage: guiders [-v|-q] [COMMAND]
+
+Commands:
+  run [--config <path>]    Listen for the bindings in the config, the default command
+  run [--] <command>...    Run <command> whenever the home button is released
+  list [--json]            Show the connected controllers and what they support
+  monitor                  Print the events of every controller as they come in,
+                           except those a daemon with grab = true holds
+  check [--config <path>]  Validate the config and its actions
+  learn [--append] [--config <path>] [--] [<command>...]
+                           Wait for a button press and show its name, with --append
+                           add a binding running <command> on it to the config,
+                           like monitor not on the controllers a daemon holds
+  ctl <request>            Talk to the running daemon, <request> is one of:
+                           list, bindings, trigger <name>, pause, resume, reload
+  install-service [--force] [--config <path>]
+                           Write a systemd user unit that runs guiders
+
+  guiders <command>... and guiders --config <path> still work as before.
+
+Options:
+  -v, -vv                  Log more: debug, or every key too
+  -q, -qq                  Log less: warnings and errors, or only errors
+  -h, --help               Show this help
+  -V, --version            Show the version
+
+The config defaults to $XDG_CONFIG_HOME/guiders/config.toml.";
+
+pub enum Command {
+    /// Listen with the bindings of the config at this path, or at the default one.
+    Run(Option<PathBuf>),
+    /// The old `guiders <command>...` form.
+    Spawn(Vec<String>),
+    List {
+        json: bool,
+    },
+    Monitor,
+    Check(Option<PathBuf>),
+    Learn {
+        append: bool,
+        config: Option<PathBuf>,
+        command: Vec<String>,
+    },
+    Ctl(Request),
+    InstallService {
+        force: bool,
+        config: Option<PathBuf>,
+    },
+    Help,
+    Version,
+}
+
+pub struct Cli {
+    pub verbosity: LevelFilter,
+    pub command: Command,
+}
+
+/// What the options after a subcommand set.
+#[derive(Default)]
+struct Options {
+    config: Option<PathBuf>,
+    force: bool,
+    json: bool,
+    append: bool,
+    /// Whatever follows `--` or the first argument that isn't an option.
+    rest: Vec<String>,
+}
+
+pub fn parse(args: Vec<String>) -> Result<Cli, Errors> {
+    let mut verbosity = 0;
+    let mut args = args.into_iter().peekable();
+    while let Some(v) = args.peek().and_then(|arg| verbosity_of(arg)) {
+        verbosity += v;
+        args.next();
+    }
+
+    let command = match args.next() {
+        None => Command::Run(None),
+        Some(first) => match first.as_str() {
+            "-h" | "--help" | "help" => Command::Help,
+            "-V" | "--version" => Command::Version,
+            "run" => {
+                let options = options(args, &mut verbosity, &["--config"], true)?;
+                if !options.rest.is_empty() && options.config.is_some() {
+                    return Err(Errors::Usage(
+                        "a command and '--config' can't be combined".to_string(),
+                    ));
+                }
+                if options.rest.is_empty() {
+                    Command::Run(options.config)
+                } else {
+                    Command::Spawn(options.rest)
+                }
+            }
+            "list" => Command::List {
+                json: options(args, &mut verbosity, &["--json"], false)?.json,
+            },
+            "monitor" => {
+                options(args, &mut verbosity, &[], false)?;
+                Command::Monitor
+            }
+            "check" => Command::Check(options(args, &mut verbosity, &["--config"], false)?.config),
+            "learn" => {
+                let options = options(args, &mut verbosity, &["--append", "--config"], true)?;
+                Command::Learn {
+                    append: options.append,
+                    config: options.config,
+                    command: options.rest,
+                }
+            }
+            "ctl" => Command::Ctl(ctl_request(args.collect())?),
+            "install-service" => {
+                let options = options(args, &mut verbosity, &["--config", "--force"], false)?;
+                Command::InstallService {
+                    force: options.force,
+                    config: options.config,
+                }
+            }
+            _ if first == "--config" || first.starts_with("--config=") => Command::Run(
+                options(
+                    iter::once(first).chain(args),
+                    &mut verbosity,
+                    &["--config"],
+                    false,
+                )?
+                .config,
+            ),
+            _ if first.starts_with('-') => return Err(unexpected(&first)),
+            _ => Command::Spawn(iter::once(first).chain(args).collect()),
+        },
+    };
+
+    Ok(Cli {
+        verbosity: match verbosity {
+            ..=-2 => LevelFilter::Error,
+            -1 => LevelFilter::Warn,
+            0 => LevelFilter::Info,
+            1 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        },
+        command,
+    })
+}
+
+/// Parses the options of a subcommand, `allowed` being the ones it takes besides `-v`/`-q`.
+/// With `rest`, the first argument that isn't an option starts a command line.
+fn options(
+    args: impl IntoIterator<Item = String>,
+    verbosity: &mut i32,
+    allowed: &[&str],
+    rest: bool,
+) -> Result<Options, Errors> {
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if let Some(v) = verbosity_of(&arg) {
+            *verbosity += v;
+            continue;
+        }
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => (name, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        match name {
+            "--config" if allowed.contains(&"--config") => {
+                let path = value
+                    .or_else(|| args.next())
+                    .ok_or_else(|| Errors::Usage("'--config' needs a path".to_string()))?;
+                options.config = Some(PathBuf::from(path));
+            }
+            "--force" if allowed.contains(&"--force") => options.force = true,
+            "--json" if allowed.contains(&"--json") => options.json = true,
+            "--append" if allowed.contains(&"--append") => options.append = true,
+            "--" if rest => {
+                options.rest = args.collect();
+                break;
+            }
+            _ if rest && !arg.starts_with('-') => {
+                options.rest = iter::once(arg).chain(args).collect();
+                break;
+            }
+            _ => return Err(unexpected(&arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn ctl_request(args: Vec<String>) -> Result<Request, Errors> {
+    let mut args = args.into_iter();
+    let request = match args.next().as_deref() {
+        Some("list") => Request::List,
+        Some("bindings") => Request::Bindings,
+        Some("trigger") => Request::Trigger {
+            name: args
+                .next()
+                .ok_or_else(|| Errors::Usage("'ctl trigger' needs a binding name".to_string()))?,
+        },
+        Some("pause") => Request::Pause,
+        Some("resume") => Request::Resume,
+        Some("reload") => Request::Reload,
+        Some(other) => return Err(unexpected(other)),
+        None => return Err(Errors::Usage("'ctl' needs a request".to_string())),
+    };
+    match args.next() {
+        Some(arg) => Err(unexpected(&arg)),
+        None => Ok(request),
+    }
+}
+
+/// +1 per `v` and -1 per `q` of flags like `-v`, `-vv` or `-q`.
+fn verbosity_of(arg: &str) -> Option<i32> {
+    let flags = arg.strip_prefix('-')?;
+    if flags.is_empty() || !flags.chars().all(|c| c == 'v' || c == 'q') {
+        return None;
+    }
+    Some(flags.chars().map(|c| if c == 'v' { 1 } else { -1 }).sum())
+}
+
+fn unexpected(arg: &str) -> Errors {
+    Errors::Usage(format!("unexpected '{arg}'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn parse(args: &[&str]) -> Result<Cli, Errors> {
+        super::parse(args.iter().map(|arg| arg.to_string()).collect())
+    }
+
+    fn command(args: &[&str]) -> Command {
+        parse(args).unwrap().command
+    }
+
+    fn verbosity(args: &[&str]) -> LevelFilter {
+        parse(args).unwrap().verbosity
+    }
+
+    #[test]
+    fn bare_command_still_spawns() {
+        assert!(matches!(
+            command(&["steam", "-bigpicture"]),
+            Command::Spawn(command) if command == ["steam", "-bigpicture"]
+        ));
+        assert!(matches!(
+            command(&["-v", "steam"]),
+            Command::Spawn(command) if command == ["steam"]
+        ));
+    }
+
+    #[test]
+    fn bare_config_still_runs() {
+        assert!(matches!(
+            command(&["--config", "x.toml"]),
+            Command::Run(Some(path)) if path == Path::new("x.toml")
+        ));
+        assert!(matches!(
+            command(&["--config=x.toml"]),
+            Command::Run(Some(path)) if path == Path::new("x.toml")
+        ));
+        assert!(matches!(command(&[]), Command::Run(None)));
+    }
+
+    #[test]
+    fn run_takes_a_command_after_dashes() {
+        assert!(matches!(
+            command(&["run", "--", "--not-an-option"]),
+            Command::Spawn(command) if command == ["--not-an-option"]
+        ));
+        assert!(matches!(
+            command(&["run", "steam"]),
+            Command::Spawn(command) if command == ["steam"]
+        ));
+    }
+
+    #[test]
+    fn run_rejects_a_command_with_a_config() {
+        assert!(matches!(
+            parse(&["run", "--config", "x.toml", "steam"]),
+            Err(Errors::Usage(_))
+        ));
+    }
+
+    #[test]
+    fn verbosity_flags_add_up() {
+        assert_eq!(verbosity(&[]), LevelFilter::Info);
+        assert_eq!(verbosity(&["-v"]), LevelFilter::Debug);
+        assert_eq!(verbosity(&["-vv"]), LevelFilter::Trace);
+        assert_eq!(verbosity(&["-v", "-v", "monitor"]), LevelFilter::Trace);
+        assert_eq!(verbosity(&["-q"]), LevelFilter::Warn);
+        assert_eq!(verbosity(&["list", "-qq"]), LevelFilter::Error);
+        assert_eq!(verbosity(&["-vq"]), LevelFilter::Info);
+    }
+
+    #[test]
+    fn unknown_options_are_rejected() {
+        assert!(matches!(parse(&["--nope"]), Err(Errors::Usage(_))));
+        assert!(matches!(parse(&["list", "--force"]), Err(Errors::Usage(_))));
+    }
+}
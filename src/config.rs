@@ -0,0 +1,662 @@
+//! Bindings file, read from `--config <path>` or `$XDG_CONFIG_HOME/guiders/config.toml`.
+//! It's read again on SIGHUP, or whenever it changes with `watch = true`:
+//!
+//! ```toml
+//! watch = true
+//! grab = true
+//! hold_ms = 800
+//! multi_tap_ms = 300
+//!
+//! [[binding]]
+//! name = "bigpicture"
+//! button = "BTN_MODE"
+//! trigger = "release"
+//! command = ["steam", "steam://open/bigpicture"]
+//!
+//! [[binding]]
+//! button = "BTN_MODE"
+//! trigger = "hold"
+//! command = ["systemctl", "suspend"]
+//!
+//! [[binding]]
+//! button = "BTN_MODE"
+//! taps = 2
+//! command = ["rofi", "-show", "drun"]
+//! concurrency = "ignore"
+//!
+//! [[binding]]
+//! button = "BTN_SELECT"
+//! trigger = "press"
+//! command = ["wvkbd-mobintl"]
+//! concurrency = "toggle"
+//! kill_timeout_ms = 2000
+//! kill_on_exit = true
+//!
+//! [[binding]]
+//! button = ["BTN_MODE", "BTN_START"]
+//! command = ["pkill", "-f", "steam"]
+//!
+//! # Right trigger pulled all the way.
+//! [[binding]]
+//! axis = "ABS_RZ"
+//! threshold = 0.95
+//! command = ["screenshot"]
+//!
+//! # Left stick pushed left past 90%, again once it came back within 70%.
+//! [[binding]]
+//! axis = "ABS_X"
+//! threshold = -0.9
+//! hysteresis = 0.2
+//! command = ["playerctl", "previous"]
+//! ```
+//!
+//! Axes are normalised with their range from the device: those that go both ways, like
+//! sticks, the d-pad hat and anything with a dead zone, to -1..1 around the middle of their
+//! range, and those that don't, like most triggers, to 0..1. A positive `threshold` is
+//! crossed going above it, a negative one going below it.
+//! `press`, the default for axes, fires on crossing it, `release` on coming back past the
+//! `hysteresis` band.
+//!
+//! With `grab = true` games don't see the bound buttons: each controller is grabbed and
+//! everything else goes through a virtual copy of it. Buttons that are only part of chords
+//! and axes still go through. Unlike the bindings, `grab` is only read at startup.
+//! While it runs, `guiders monitor` and `guiders learn` get nothing from the grabbed
+//! controllers, stop it first.
+
+use evdev::{AbsoluteAxisCode, KeyCode};
+use serde::Deserialize;
+use std::{
+    env, fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+use toml::Table;
+
+use crate::{Errors, supervisor::Concurrency};
+
+/// Key codes of the home button, as reported by most controllers.
+const HOME_BUTTONS: [KeyCode; 2] = [KeyCode::BTN_MODE, KeyCode::KEY_MENU];
+
+const DEFAULT_HOLD_MS: u64 = 800;
+const DEFAULT_MULTI_TAP_MS: u64 = 300;
+const DEFAULT_KILL_TIMEOUT_MS: u64 = 3000;
+const DEFAULT_HYSTERESIS: f32 = 0.1;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Reload as soon as the file changes, not only on SIGHUP.
+    #[serde(default)]
+    pub watch: bool,
+    /// Hide the bound buttons from other programs, see [`Listener::grab`](crate::Listener::grab).
+    #[serde(default)]
+    pub grab: bool,
+    /// Default for bindings that don't set their own `hold_ms`.
+    #[serde(default = "default_hold_ms")]
+    pub hold_ms: u64,
+    /// Default for bindings that don't set their own `multi_tap_ms`.
+    #[serde(default = "default_multi_tap_ms")]
+    pub multi_tap_ms: u64,
+    #[serde(default, rename = "binding")]
+    pub bindings: Vec<Binding>,
+}
+
+fn default_hold_ms() -> u64 {
+    DEFAULT_HOLD_MS
+}
+
+fn default_multi_tap_ms() -> u64 {
+    DEFAULT_MULTI_TAP_MS
+}
+
+fn default_taps() -> u8 {
+    1
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Binding {
+    /// Lets `guiders ctl trigger <name>` run this binding.
+    pub name: Option<String>,
+    /// One button, or several that have to be held together.
+    #[serde(default)]
+    pub button: Buttons,
+    /// An absolute axis instead of a button, see `threshold`.
+    pub axis: Option<Axis>,
+    /// Normalised position of the `axis` at which the binding fires, from -1 to 1.
+    pub threshold: Option<f32>,
+    /// How far back the `axis` has to go before the threshold counts as crossed again.
+    pub hysteresis: Option<f32>,
+    /// `release` by default for buttons, `press` for axes.
+    #[serde(default)]
+    pub trigger: Trigger,
+    /// How long the button has to be held down for a `hold` trigger.
+    pub hold_ms: Option<u64>,
+    /// Number of quick presses in a row for a `release` trigger, 2 for a double tap.
+    #[serde(default = "default_taps")]
+    pub taps: u8,
+    /// How long after a release the next tap may start and still count.
+    pub multi_tap_ms: Option<u64>,
+    /// Shorthand for a `spawn` action running this command.
+    #[serde(default)]
+    pub command: Vec<String>,
+    /// `{ type = "...", ... }`, see [`Actions`](crate::action::Actions).
+    pub action: Option<Table>,
+    /// What a press does while the command from the previous one is still running.
+    #[serde(default)]
+    pub concurrency: Concurrency,
+    /// How long a `toggle` or `kill_on_exit` waits after SIGTERM before sending SIGKILL.
+    pub kill_timeout_ms: Option<u64>,
+    /// Stop whatever this binding started when guiders exits.
+    #[serde(default)]
+    pub kill_on_exit: bool,
+}
+
+impl Binding {
+    /// A single-button binding with the default thresholds and no action.
+    pub fn new(button: Button, trigger: Trigger) -> Binding {
+        Binding {
+            name: None,
+            button: Buttons(vec![button]),
+            axis: None,
+            threshold: None,
+            hysteresis: None,
+            trigger,
+            hold_ms: None,
+            taps: 1,
+            multi_tap_ms: None,
+            command: Vec::new(),
+            action: None,
+            concurrency: Concurrency::default(),
+            kill_timeout_ms: None,
+            kill_on_exit: false,
+        }
+    }
+
+    /// A binding that fires once all `buttons` are held down together.
+    pub fn chord(buttons: Vec<Button>) -> Binding {
+        Binding {
+            button: Buttons(buttons),
+            ..Binding::new(Button(KeyCode::KEY_RESERVED), Trigger::Press)
+        }
+    }
+
+    /// A binding that fires once `axis` crosses `threshold`, see the [module docs](self).
+    pub fn axis(axis: Axis, threshold: f32, trigger: Trigger) -> Binding {
+        Binding {
+            button: Buttons::default(),
+            axis: Some(axis),
+            threshold: Some(threshold),
+            ..Binding::new(Button(KeyCode::KEY_RESERVED), trigger)
+        }
+    }
+
+    pub fn hold_duration(&self) -> Duration {
+        Duration::from_millis(self.hold_ms.unwrap_or(DEFAULT_HOLD_MS))
+    }
+
+    pub fn multi_tap_window(&self) -> Duration {
+        Duration::from_millis(self.multi_tap_ms.unwrap_or(DEFAULT_MULTI_TAP_MS))
+    }
+
+    pub fn kill_timeout(&self) -> Duration {
+        Duration::from_millis(self.kill_timeout_ms.unwrap_or(DEFAULT_KILL_TIMEOUT_MS))
+    }
+
+    pub fn hysteresis(&self) -> f32 {
+        self.hysteresis.unwrap_or(DEFAULT_HYSTERESIS)
+    }
+
+    /// The axis or the button(s) it fires on, as written in the config.
+    pub fn input(&self) -> String {
+        match &self.axis {
+            Some(axis) => axis.to_string(),
+            None => self.button.to_string(),
+        }
+    }
+
+    /// The `action` table, with `command` turned into a `spawn` action.
+    pub fn action_options(&self) -> Option<Table> {
+        if let Some(action) = &self.action {
+            return Some(action.clone());
+        }
+        if self.command.is_empty() {
+            return None;
+        }
+        let mut action = Table::new();
+        action.insert("type".to_string(), "spawn".into());
+        action.insert("command".to_string(), self.command.clone().into());
+        Some(action)
+    }
+
+    /// Whether `other` is this binding, as read again from an edited config: same name,
+    /// or for unnamed bindings the same input, trigger and action.
+    pub(crate) fn same_as(&self, other: &Binding) -> bool {
+        match (&self.name, &other.name) {
+            (Some(name), Some(other)) => name == other,
+            (None, None) => {
+                self.input() == other.input()
+                    && self.trigger == other.trigger
+                    && self.taps == other.taps
+                    && self.threshold == other.threshold
+                    && self.action_options() == other.action_options()
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether this binding fires on a combination of buttons held together.
+    pub fn is_chord(&self) -> bool {
+        self.button.0.len() > 1
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.button.0.is_empty() == self.axis.is_none() {
+            return Err("binding needs either a 'button' or an 'axis'".to_string());
+        }
+        if self.command.is_empty() == self.action.is_none() {
+            return Err(format!(
+                "binding on {} needs either a 'command' or an 'action'",
+                self.input()
+            ));
+        }
+        if self.axis.is_some() {
+            return self.validate_axis();
+        }
+        if self.threshold.is_some() || self.hysteresis.is_some() {
+            return Err(format!(
+                "binding on {}: 'threshold' and 'hysteresis' only work with an 'axis'",
+                self.button
+            ));
+        }
+        if self.taps == 0 {
+            return Err(format!("binding on {} needs at least 1 tap", self.button));
+        }
+        if self.taps > 1 && self.trigger != Trigger::Release {
+            return Err(format!(
+                "binding on {}: 'taps' only works with the release trigger",
+                self.button
+            ));
+        }
+        if self.is_chord() && (self.taps > 1 || self.trigger == Trigger::Hold) {
+            return Err(format!(
+                "binding on {}: chords fire once all buttons are down and don't support 'taps' or 'hold'",
+                self.button
+            ));
+        }
+        Ok(())
+    }
+
+    fn validate_axis(&self) -> Result<(), String> {
+        let axis = self.input();
+        match self.threshold {
+            None => return Err(format!("binding on {axis} needs a 'threshold'")),
+            Some(threshold) if threshold == 0.0 || !(-1.0..=1.0).contains(&threshold) => {
+                return Err(format!(
+                    "binding on {axis}: 'threshold' has to be between -1 and 1, and not 0"
+                ));
+            }
+            Some(_) => {}
+        }
+        if !(0.0..=1.0).contains(&self.hysteresis()) {
+            return Err(format!(
+                "binding on {axis}: 'hysteresis' has to be between 0 and 1"
+            ));
+        }
+        if self.trigger == Trigger::Hold || self.taps > 1 {
+            return Err(format!(
+                "binding on {axis}: axes fire on press or release and don't support 'taps' or 'hold'"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// An absolute axis, written in the config by its evdev name (`ABS_RZ`, `ABS_HAT0X`, ...).
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Axis(pub AbsoluteAxisCode);
+
+impl FromStr for Axis {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        AbsoluteAxisCode::from_str(&name.to_ascii_uppercase())
+            .map(Axis)
+            .map_err(|_| {
+                format!("unknown axis '{name}', expected an evdev axis name like 'ABS_RZ' or 'ABS_HAT0X'")
+            })
+    }
+}
+
+impl TryFrom<String> for Axis {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        name.parse()
+    }
+}
+
+impl fmt::Debug for Axis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl fmt::Display for Axis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+/// A key or button, written in the config by its evdev name (`BTN_MODE`, `KEY_MENU`, ...).
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Button(pub KeyCode);
+
+impl FromStr for Button {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        KeyCode::from_str(&name.to_ascii_uppercase())
+            .map(Button)
+            .map_err(|_| {
+                format!("unknown button '{name}', expected an evdev key name like 'BTN_MODE' or 'KEY_MENU'")
+            })
+    }
+}
+
+impl TryFrom<String> for Button {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        name.parse()
+    }
+}
+
+impl fmt::Debug for Button {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl fmt::Display for Button {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+/// The button(s) of a binding, either `"BTN_MODE"` or `["BTN_MODE", "BTN_START"]`.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Buttons(pub Vec<Button>);
+
+impl<'de> Deserialize<'de> for Buttons {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = Buttons;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a button name or a list of button names")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, name: &str) -> Result<Buttons, E> {
+                name.parse().map(|b| Buttons(vec![b])).map_err(E::custom)
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> Result<Buttons, A::Error> {
+                let mut buttons = Vec::new();
+                while let Some(button) = seq.next_element()? {
+                    buttons.push(button);
+                }
+                Ok(Buttons(buttons))
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+impl Buttons {
+    pub fn contains(&self, key: KeyCode) -> bool {
+        self.0.iter().any(|b| b.0 == key)
+    }
+}
+
+impl fmt::Debug for Buttons {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+impl fmt::Display for Buttons {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, button) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, "+")?;
+            }
+            write!(f, "{button}")?;
+        }
+        Ok(())
+    }
+}
+
+/// When a binding runs its action.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Trigger {
+    /// As soon as the button goes down.
+    Press,
+    /// Once the button is let go, unless a `hold` binding on it already fired.
+    /// With `taps` above 1, once it was pressed and let go that many times in a row.
+    #[default]
+    Release,
+    /// Once the button has been held down for `hold_ms`.
+    Hold,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, Errors> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| Errors::ConfigRead(path.display().to_string(), e.to_string()))?;
+        Config::parse(&contents, path)
+    }
+
+    /// Like [`Config::load`] with the `contents` of the file at `path`, which only shows up in errors.
+    pub fn parse(contents: &str, path: &Path) -> Result<Config, Errors> {
+        let mut config: Config = toml::from_str(contents)
+            .map_err(|e| Errors::ConfigParse(path.display().to_string(), e.to_string()))?;
+
+        let invalid = |e: String| Errors::ConfigParse(path.display().to_string(), e);
+        // Serde can't default `trigger` by whether there's an axis, so look at what was written.
+        let tables: Table = toml::from_str(contents).map_err(|e| invalid(e.to_string()))?;
+        let written: Vec<bool> = tables
+            .get("binding")
+            .and_then(|bindings| bindings.as_array())
+            .map(|bindings| {
+                bindings
+                    .iter()
+                    .map(|b| b.get("trigger").is_some())
+                    .collect()
+            })
+            .unwrap_or_default();
+        for (binding, &trigger) in config.bindings.iter_mut().zip(&written) {
+            if binding.axis.is_some() && !trigger {
+                binding.trigger = Trigger::Press;
+            }
+        }
+        for binding in &mut config.bindings {
+            binding.hold_ms.get_or_insert(config.hold_ms);
+            binding.multi_tap_ms.get_or_insert(config.multi_tap_ms);
+            binding.validate().map_err(invalid)?;
+        }
+        for (i, binding) in config.bindings.iter().enumerate() {
+            if let Some(name) = &binding.name
+                && config.bindings[..i]
+                    .iter()
+                    .any(|b| b.name.as_ref() == Some(name))
+            {
+                return Err(invalid(format!("more than one binding is named '{name}'")));
+            }
+        }
+        Ok(config)
+    }
+
+    /// The old `guiders <command...>` form: run `command` when the home button is released.
+    pub fn from_command(command: Vec<String>) -> Config {
+        let bindings = HOME_BUTTONS
+            .into_iter()
+            .map(|key| Binding {
+                command: command.clone(),
+                ..Binding::new(Button(key), Trigger::Release)
+            })
+            .collect();
+        Config {
+            watch: false,
+            grab: false,
+            hold_ms: DEFAULT_HOLD_MS,
+            multi_tap_ms: DEFAULT_MULTI_TAP_MS,
+            bindings,
+        }
+    }
+
+    /// `$XDG_CONFIG_HOME/guiders/config.toml`, falling back to `~/.config`.
+    pub fn default_path() -> Option<PathBuf> {
+        let base = env::var_os("XDG_CONFIG_HOME")
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|v| PathBuf::from(v).join(".config")))?;
+        Some(base.join("guiders").join("config.toml"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(contents: &str) -> Result<Config, Errors> {
+        Config::parse(contents, Path::new("config.toml"))
+    }
+
+    #[test]
+    fn axes_fire_on_press_by_default() {
+        let config = parse(
+            r#"
+            [[binding]]
+            axis = "ABS_RZ"
+            threshold = 0.95
+            command = ["true"]
+
+            [[binding]]
+            axis = "ABS_RZ"
+            threshold = 0.95
+            trigger = "release"
+            command = ["true"]
+
+            [[binding]]
+            button = "BTN_MODE"
+            command = ["true"]
+            "#,
+        )
+        .unwrap();
+        let triggers: Vec<Trigger> = config.bindings.iter().map(|b| b.trigger).collect();
+        assert_eq!(
+            triggers,
+            [Trigger::Press, Trigger::Release, Trigger::Release]
+        );
+    }
+
+    /// The message of the error that `contents` fails with.
+    fn error(contents: &str) -> String {
+        match parse(contents) {
+            Err(Errors::ConfigParse(_, e)) => e,
+            Err(e) => panic!("unexpected error {e}"),
+            Ok(_) => panic!("parsed fine"),
+        }
+    }
+
+    #[test]
+    fn names_are_case_insensitive() {
+        assert_eq!("btn_mode".parse::<Button>(), Ok(Button(KeyCode::BTN_MODE)));
+        let config = parse("[[binding]]\nbutton = \"key_menu\"\ncommand = [\"true\"]").unwrap();
+        assert_eq!(
+            config.bindings[0].button,
+            Buttons(vec![Button(KeyCode::KEY_MENU)])
+        );
+    }
+
+    #[test]
+    fn unknown_names_are_rejected() {
+        assert!(
+            "BTN_NOPE"
+                .parse::<Button>()
+                .unwrap_err()
+                .contains("unknown button 'BTN_NOPE'")
+        );
+        let e = error("[[binding]]\nbutton = \"BTN_NOPE\"\ncommand = [\"true\"]");
+        assert!(e.contains("unknown button 'BTN_NOPE'"), "{e}");
+        let e = error("[[binding]]\naxis = \"ABS_NOPE\"\nthreshold = 0.5\ncommand = [\"true\"]");
+        assert!(e.contains("unknown axis 'ABS_NOPE'"), "{e}");
+    }
+
+    #[test]
+    fn duplicate_names_are_rejected() {
+        let e = error(
+            r#"
+            [[binding]]
+            name = "steam"
+            button = "BTN_MODE"
+            command = ["true"]
+
+            [[binding]]
+            name = "steam"
+            button = "BTN_START"
+            command = ["true"]
+            "#,
+        );
+        assert!(e.contains("more than one binding is named 'steam'"), "{e}");
+    }
+
+    #[test]
+    fn invalid_combinations_are_rejected() {
+        let cases = [
+            (
+                "button = \"BTN_MODE\"\ntaps = 2\ntrigger = \"hold\"",
+                "'taps' only works with the release trigger",
+            ),
+            (
+                "button = [\"BTN_MODE\", \"BTN_START\"]\ntrigger = \"hold\"",
+                "chords fire once all buttons are down",
+            ),
+            (
+                "button = \"BTN_MODE\"\nthreshold = 0.5",
+                "'threshold' and 'hysteresis' only work with an 'axis'",
+            ),
+            ("button = \"BTN_MODE\"\ntaps = 0", "needs at least 1 tap"),
+            ("axis = \"ABS_RZ\"", "needs a 'threshold'"),
+            (
+                "axis = \"ABS_RZ\"\nthreshold = 0.0",
+                "has to be between -1 and 1",
+            ),
+            (
+                "axis = \"ABS_RZ\"\nthreshold = 0.5\ntrigger = \"hold\"",
+                "axes fire on press or release",
+            ),
+            (
+                "button = \"BTN_MODE\"\naxis = \"ABS_RZ\"\nthreshold = 0.5",
+                "either a 'button' or an 'axis'",
+            ),
+        ];
+        for (binding, expected) in cases {
+            let e = error(&format!("[[binding]]\n{binding}\ncommand = [\"true\"]"));
+            assert!(e.contains(expected), "{binding}: {e}");
+        }
+        let e = error("[[binding]]\nbutton = \"BTN_MODE\"");
+        assert!(e.contains("needs either a 'command' or an 'action'"), "{e}");
+    }
+}
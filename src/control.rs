@@ -0,0 +1,233 @@
+//! The control socket, by default at `$XDG_RUNTIME_DIR/guiders.sock`.
+//!
+//! Clients send one JSON [`Request`] per line and get one JSON [`Response`] per line back:
+//!
+//! ```text
+//! > {"cmd":"trigger","name":"steam"}
+//! < {"ok":true}
+//! > {"cmd":"pause"}
+//! < {"ok":true,"paused":true}
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    os::{
+        fd::{AsRawFd, RawFd},
+        unix::net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+};
+
+use crate::{Errors, controller::Controller};
+
+/// What a client asks the daemon to do.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "lowercase")]
+pub enum Request {
+    /// The connected controllers.
+    List,
+    /// The active bindings.
+    Bindings,
+    /// Run the binding with this `name`, as if it fired.
+    Trigger {
+        name: String,
+    },
+    /// Stop running bindings until `resume`, controllers stay open and grabbed ones pass
+    /// their bound buttons on too.
+    Pause,
+    Resume,
+    /// Read the config again.
+    Reload,
+}
+
+/// The answer to a [`Request`], only the fields that apply to it are set.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub controllers: Option<Vec<Controller>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bindings: Option<Vec<BindingInfo>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paused: Option<bool>,
+}
+
+impl Response {
+    pub fn ok() -> Response {
+        Response {
+            ok: true,
+            ..Response::default()
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Response {
+        Response {
+            error: Some(message.into()),
+            ..Response::default()
+        }
+    }
+}
+
+/// A binding as listed by the `bindings` request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BindingInfo {
+    pub index: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub button: String,
+    /// How it fires, as in [`PressKind`](crate::PressKind)'s `Display`.
+    pub kind: String,
+    /// The action type, `None` for bindings that only report events.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<String>,
+}
+
+/// `$XDG_RUNTIME_DIR/guiders.sock`.
+pub fn default_path() -> Option<PathBuf> {
+    env::var_os("XDG_RUNTIME_DIR")
+        .filter(|v| !v.is_empty())
+        .map(|v| PathBuf::from(v).join("guiders.sock"))
+}
+
+/// Sends `request` to the daemon listening on `path` and waits for its answer.
+pub fn request(path: &Path, request: &Request) -> Result<Response, Errors> {
+    let error = |e: String| Errors::ControlSocket(path.display().to_string(), e);
+
+    let mut stream = UnixStream::connect(path).map_err(|e| error(e.to_string()))?;
+    let mut line = serde_json::to_string(request).map_err(|e| error(e.to_string()))?;
+    line.push('\n');
+    stream
+        .write_all(line.as_bytes())
+        .map_err(|e| error(e.to_string()))?;
+
+    let mut answer = String::new();
+    BufReader::new(stream)
+        .read_line(&mut answer)
+        .map_err(|e| error(e.to_string()))?;
+    serde_json::from_str(&answer).map_err(|e| error(format!("invalid response: {e}")))
+}
+
+/// The listening socket and the clients connected to it, all nonblocking.
+pub(crate) struct Server {
+    path: PathBuf,
+    listener: UnixListener,
+    connections: HashMap<RawFd, Connection>,
+}
+
+struct Connection {
+    stream: UnixStream,
+    /// What was read past the last complete line.
+    buffer: Vec<u8>,
+}
+
+impl Server {
+    /// Listens on `path`, replacing a stale socket but not one that another daemon still answers on.
+    pub fn bind(path: &Path) -> Result<Server, Errors> {
+        let error = |e: String| Errors::ControlSocket(path.display().to_string(), e);
+
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(error("another guiders is already listening".to_string()));
+            }
+            fs::remove_file(path).map_err(|e| error(e.to_string()))?;
+        }
+        let listener = UnixListener::bind(path).map_err(|e| error(e.to_string()))?;
+        listener
+            .set_nonblocking(true)
+            .map_err(|e| error(e.to_string()))?;
+
+        Ok(Server {
+            path: path.to_path_buf(),
+            listener,
+            connections: HashMap::new(),
+        })
+    }
+
+    pub fn listener(&self) -> &UnixListener {
+        &self.listener
+    }
+
+    /// Whether `fd` belongs to one of the connected clients.
+    pub fn owns(&self, fd: RawFd) -> bool {
+        self.connections.contains_key(&fd)
+    }
+
+    /// Takes the pending clients, to be added to the epoll set by their fd.
+    pub fn accept(&mut self) -> Vec<&UnixStream> {
+        let mut accepted = Vec::new();
+        while let Ok((stream, _)) = self.listener.accept() {
+            if stream.set_nonblocking(true).is_err() {
+                continue;
+            }
+            let fd = stream.as_raw_fd();
+            self.connections.insert(
+                fd,
+                Connection {
+                    stream,
+                    buffer: Vec::new(),
+                },
+            );
+            accepted.push(fd);
+        }
+        accepted
+            .into_iter()
+            .filter_map(|fd| self.connections.get(&fd).map(|c| &c.stream))
+            .collect()
+    }
+
+    /// Reads what the client behind `fd` sent, returning its complete requests.
+    ///
+    /// `None` once the client hung up, it should then be [`close`](Server::close)d.
+    pub fn read(&mut self, fd: RawFd) -> Option<Vec<Result<Request, String>>> {
+        let connection = self.connections.get_mut(&fd)?;
+        let mut chunk = [0; 1024];
+        let open = loop {
+            match connection.stream.read(&mut chunk) {
+                Ok(0) => break false,
+                Ok(n) => connection.buffer.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break true,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => break false,
+            }
+        };
+
+        let mut requests = Vec::new();
+        while let Some(end) = connection.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = connection.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            if !line.trim().is_empty() {
+                requests.push(serde_json::from_str(line.trim()).map_err(|e| e.to_string()));
+            }
+        }
+        // Whatever was asked before hanging up still gets answered.
+        (open || !requests.is_empty()).then_some(requests)
+    }
+
+    /// Sends `response` to the client behind `fd`, false if it can't take it anymore.
+    pub fn reply(&mut self, fd: RawFd, response: &Response) -> bool {
+        let Some(connection) = self.connections.get_mut(&fd) else {
+            return false;
+        };
+        let Ok(mut line) = serde_json::to_string(response) else {
+            return false;
+        };
+        line.push('\n');
+        connection.stream.write_all(line.as_bytes()).is_ok()
+    }
+
+    /// Forgets the client behind `fd`, returning its stream so it can leave the epoll set first.
+    pub fn close(&mut self, fd: RawFd) -> Option<UnixStream> {
+        self.connections.remove(&fd).map(|c| c.stream)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,51 @@
+use evdev::BusType;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// A connected controller, as seen by udev and evdev.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Controller {
+    pub devnode: String,
+    pub syspath: PathBuf,
+    /// The evdev name, e.g. "Xbox Wireless Controller".
+    pub name: String,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub version: u16,
+    pub bus_type: BusType,
+    /// Usually the bluetooth MAC of wireless pads, empty when the driver doesn't report one.
+    pub uniq: String,
+    pub phys: String,
+}
+
+impl Controller {
+    pub(crate) fn new(devnode: String, udev_device: &udev::Device, device: &evdev::Device) -> Self {
+        let id = device.input_id();
+        Controller {
+            devnode,
+            syspath: udev_device.syspath().to_path_buf(),
+            name: device.name().unwrap_or("Nameless device").to_string(),
+            vendor_id: id.vendor(),
+            product_id: id.product(),
+            version: id.version(),
+            bus_type: id.bus_type(),
+            uniq: device.unique_name().unwrap_or_default().to_string(),
+            phys: device.physical_path().unwrap_or_default().to_string(),
+        }
+    }
+
+    /// Stands in for a controller when a binding is triggered through the control socket.
+    pub(crate) fn control_socket() -> Self {
+        Controller {
+            devnode: String::new(),
+            syspath: PathBuf::new(),
+            name: "control socket".to_string(),
+            vendor_id: 0,
+            product_id: 0,
+            version: 0,
+            bus_type: BusType::BUS_VIRTUAL,
+            uniq: String::new(),
+            phys: String::new(),
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! udev side of finding controllers, shared by the event loop and the async streams.
+
+use serde::{Deserialize, Serialize};
+use udev::{Enumerator, MonitorBuilder, MonitorSocket};
+
+use crate::{Errors, controller::Controller, uinput};
+
+/// Every device in the `input` subsystem that udev currently knows about.
+pub fn scan() -> Result<Vec<udev::Device>, Errors> {
+    let mut enumerator = Enumerator::new().map_err(|_| Errors::UdevError)?;
+    enumerator
+        .match_subsystem("input")
+        .map_err(|_| Errors::UdevSubsystem)?;
+    let devices = enumerator
+        .scan_devices()
+        .map_err(|_| Errors::UdevDeviceScan)?;
+    Ok(devices.collect())
+}
+
+/// A nonblocking udev monitor for `input` devices being added and removed.
+pub fn monitor() -> Result<MonitorSocket, Errors> {
+    MonitorBuilder::new()
+        .and_then(|v| v.match_subsystem("input"))
+        .and_then(|v| v.listen())
+        .map_err(|_| Errors::UdevMonitor)
+}
+
+/// The evdev devnode of `device`, if it is a controller worth listening to.
+pub fn controller_devnode(device: &udev::Device) -> Result<String, Errors> {
+    device
+        .properties()
+        .find(|v| v.name() == "ID_INPUT_JOYSTICK" && v.value() == "1")
+        .ok_or(Errors::NotController)?;
+    // The parent inputN and the jsN node of a pad are tagged as joysticks too.
+    if !device.sysname().to_string_lossy().starts_with("event") {
+        return Err(Errors::NotEventDevice);
+    }
+    let phys = device
+        .parent()
+        .and_then(|input| input.attribute_value("phys").map(|v| v.to_owned()));
+    if phys.is_some_and(|phys| uinput::is_virtual(&phys.to_string_lossy())) {
+        return Err(Errors::VirtualDevice);
+    }
+    device
+        .devnode()
+        .map(|v| v.to_string_lossy().to_string())
+        .ok_or(Errors::NoDevicePath)
+}
+
+/// Everything known about a connected controller, for matching rules and debugging.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Details {
+    #[serde(flatten)]
+    pub controller: Controller,
+    /// Evdev names of the keys and buttons it reports.
+    pub keys: Vec<String>,
+    pub axes: Vec<Axis>,
+    /// The force-feedback effects it supports, empty if it can't rumble.
+    pub force_feedback: Vec<String>,
+    /// LED class devices of the pad, like its player number lights.
+    pub leds: Vec<String>,
+    /// The battery of wireless pads.
+    pub power_supply: Option<PowerSupply>,
+}
+
+/// An absolute axis and its range, from the device's `AbsInfo`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Axis {
+    pub name: String,
+    pub min: i32,
+    pub max: i32,
+    pub flat: i32,
+    pub fuzz: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PowerSupply {
+    pub name: String,
+    /// Charge in percent.
+    pub capacity: Option<u8>,
+    /// `Charging`, `Discharging`, `Full`, ...
+    pub status: Option<String>,
+}
+
+/// Like [`controllers`], with what each of them can do.
+pub fn details() -> Result<Vec<Details>, Errors> {
+    let mut details: Vec<Details> = scan()?
+        .iter()
+        .filter_map(|device| {
+            let devnode = controller_devnode(device).ok()?;
+            let evdev_device = evdev::Device::open(&devnode).ok()?;
+            Some(describe(devnode, device, &evdev_device))
+        })
+        .collect();
+    details.sort_by(|a, b| a.controller.devnode.cmp(&b.controller.devnode));
+    Ok(details)
+}
+
+fn describe(devnode: String, device: &udev::Device, evdev_device: &evdev::Device) -> Details {
+    let keys = evdev_device
+        .supported_keys()
+        .map(|keys| keys.iter().map(|key| format!("{key:?}")).collect())
+        .unwrap_or_default();
+    let axes = evdev_device
+        .get_absinfo()
+        .map(|axes| {
+            axes.map(|(axis, info)| Axis {
+                name: format!("{axis:?}"),
+                min: info.minimum(),
+                max: info.maximum(),
+                flat: info.flat(),
+                fuzz: info.fuzz(),
+            })
+            .collect()
+        })
+        .unwrap_or_default();
+    let force_feedback = evdev_device
+        .supported_ff()
+        .map(|effects| effects.iter().map(|effect| format!("{effect:?}")).collect())
+        .unwrap_or_default();
+
+    // LEDs and batteries hang off the HID or USB device, above eventN and inputN.
+    let physical = device.parent().and_then(|input| input.parent());
+    let leds = physical
+        .as_ref()
+        .map(|parent| {
+            children(parent, "leds")
+                .iter()
+                .map(|led| led.sysname().to_string_lossy().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    let power_supply = physical.as_ref().and_then(|parent| {
+        let supply = children(parent, "power_supply").into_iter().next()?;
+        let attribute = |name: &str| {
+            supply
+                .attribute_value(name)
+                .map(|v| v.to_string_lossy().trim().to_string())
+        };
+        Some(PowerSupply {
+            name: supply.sysname().to_string_lossy().to_string(),
+            capacity: attribute("capacity").and_then(|v| v.parse().ok()),
+            status: attribute("status"),
+        })
+    });
+
+    Details {
+        controller: Controller::new(devnode, device, evdev_device),
+        keys,
+        axes,
+        force_feedback,
+        leds,
+        power_supply,
+    }
+}
+
+/// The devices of `subsystem` below `parent`.
+fn children(parent: &udev::Device, subsystem: &str) -> Vec<udev::Device> {
+    let Ok(mut enumerator) = Enumerator::new() else {
+        return Vec::new();
+    };
+    if enumerator.match_parent(parent).is_err() || enumerator.match_subsystem(subsystem).is_err() {
+        return Vec::new();
+    }
+    enumerator
+        .scan_devices()
+        .map(|devices| devices.collect())
+        .unwrap_or_default()
+}
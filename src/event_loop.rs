@@ -0,0 +1,826 @@
+use evdev::{AbsInfo, AbsoluteAxisCode, EventSummary, SynchronizationCode};
+use log::{debug, error, info, trace, warn};
+use nix::{
+    errno::Errno,
+    sys::{
+        epoll::{Epoll, EpollCreateFlags, EpollEvent, EpollFlags, EpollTimeout},
+        eventfd::EventFd,
+        inotify::{AddWatchFlags, InitFlags, Inotify},
+        signal::{SigSet, SigmaskHow, Signal},
+        signalfd::{SfdFlags, SignalFd},
+    },
+};
+use std::{
+    ffi::OsString,
+    io,
+    os::fd::{AsFd, AsRawFd, RawFd},
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+use udev::MonitorSocket;
+
+use crate::{
+    Errors,
+    action::{Action, Actions, Context},
+    config::Binding,
+    control::{self, BindingInfo, Request, Response},
+    controller::Controller,
+    device,
+    gesture::Gestures,
+    listener::{ButtonEvent, Handlers, Listener, PressKind, ReloadHandler},
+    notify::Notifier,
+    registry::{Entry, Registry},
+    supervisor::Supervisor,
+    uinput::Passthrough,
+};
+
+/// A binding that fired on a controller at a given time, with the reload it belongs to.
+type Fired = (Arc<Controller>, usize, SystemTime, usize);
+
+/// Epoll tokens of the udev monitor, the signalfd, the control socket, the config watch,
+/// the stopper and the children's exits, devices and control clients use their fd.
+const MONITOR: u64 = u64::MAX;
+const SIGNALS: u64 = u64::MAX - 1;
+const CONTROL: u64 = u64::MAX - 2;
+const WATCH: u64 = u64::MAX - 3;
+const STOP: u64 = u64::MAX - 4;
+const CHILDREN: u64 = u64::MAX - 5;
+
+/// Watches the udev monitor and every open controller from a single thread.
+pub(crate) struct EventLoop {
+    epoll: Epoll,
+    monitor: MonitorSocket,
+    signals: SignalFd,
+    /// Unblocks the signals the loop reads once it's dropped.
+    _mask: RestoreMask,
+    registry: Registry,
+    supervisor: Supervisor,
+    bindings: Arc<Vec<Binding>>,
+    /// The action of each binding, by index.
+    actions: Vec<Option<Box<dyn Action>>>,
+    /// Builds the actions of reloaded bindings.
+    factories: Actions,
+    /// How many bindings at the end were given in code, they survive a reload.
+    bound: usize,
+    /// What the supervisor files the children of each binding under, by index.
+    run_ids: Vec<usize>,
+    next_run_id: usize,
+    /// How many reloads went through, binding indices from before one are stale.
+    generation: usize,
+    reload: Option<ReloadHandler>,
+    /// Watches the config's directory, with the config's file name in it.
+    watch: Option<(Inotify, OsString)>,
+    control: Option<control::Server>,
+    notifier: Option<Notifier>,
+    /// Grab the controllers behind a passthrough copy.
+    grab: bool,
+    /// Set through the control socket, fired bindings are dropped meanwhile.
+    paused: bool,
+    /// Set by SIGINT, SIGTERM or a [`Stopper`](crate::Stopper).
+    stopping: bool,
+    stop: Option<Arc<EventFd>>,
+    handlers: Handlers,
+}
+
+impl EventLoop {
+    pub fn new(listener: Listener) -> Result<EventLoop, Errors> {
+        let Listener {
+            bindings,
+            actions,
+            registry: factories,
+            handlers,
+            control,
+            reload,
+            watch,
+            notify,
+            grab,
+            stop,
+        } = listener;
+        let bound = actions.iter().filter(|action| action.is_some()).count();
+        let bindings_len = bindings.len();
+        let actions = actions
+            .into_iter()
+            .zip(&bindings)
+            .map(|(action, binding)| match action {
+                Some(action) => Ok(Some(action)),
+                None => factories.build(binding),
+            })
+            .collect::<Result<Vec<_>, Errors>>()?;
+        factories.open_devices()?;
+
+        let epoll = Epoll::new(EpollCreateFlags::EPOLL_CLOEXEC).map_err(|_| Errors::Epoll)?;
+
+        // Listen before scanning, so nothing plugged in meanwhile gets missed.
+        let monitor = device::monitor()?;
+        epoll
+            .add(
+                monitor.as_fd(),
+                EpollEvent::new(EpollFlags::EPOLLIN, MONITOR),
+            )
+            .map_err(|_| Errors::Epoll)?;
+
+        // Children are reaped when SIGCHLD shows up on the signalfd or their pidfd exits,
+        // SIGHUP reloads, SIGINT and SIGTERM stop the loop.
+        let mut mask = SigSet::empty();
+        mask.add(Signal::SIGCHLD);
+        mask.add(Signal::SIGINT);
+        mask.add(Signal::SIGTERM);
+        if reload.is_some() {
+            mask.add(Signal::SIGHUP);
+        }
+        let previous = mask
+            .thread_swap_mask(SigmaskHow::SIG_BLOCK)
+            .map_err(|_| Errors::Signals)?;
+        let restore_mask = RestoreMask(previous);
+        let signals = SignalFd::with_flags(&mask, SfdFlags::SFD_NONBLOCK | SfdFlags::SFD_CLOEXEC)
+            .map_err(|_| Errors::Signals)?;
+        epoll
+            .add(
+                signals.as_fd(),
+                EpollEvent::new(EpollFlags::EPOLLIN, SIGNALS),
+            )
+            .map_err(|_| Errors::Epoll)?;
+
+        let supervisor = Supervisor::default();
+        if let Some(exits) = supervisor.exits() {
+            epoll
+                .add(exits, EpollEvent::new(EpollFlags::EPOLLIN, CHILDREN))
+                .map_err(|_| Errors::Epoll)?;
+        }
+
+        let mut event_loop = EventLoop {
+            epoll,
+            monitor,
+            signals,
+            _mask: restore_mask,
+            registry: Registry::default(),
+            supervisor,
+            bindings: Arc::new(bindings),
+            actions,
+            factories,
+            bound,
+            run_ids: (0..bindings_len).collect(),
+            next_run_id: bindings_len,
+            generation: 0,
+            reload,
+            watch: None,
+            control: None,
+            notifier: if notify { Notifier::from_env() } else { None },
+            grab,
+            paused: false,
+            stopping: false,
+            stop: None,
+            handlers,
+        };
+
+        if let Some(path) = control {
+            let server = control::Server::bind(&path)?;
+            event_loop
+                .epoll
+                .add(
+                    server.listener(),
+                    EpollEvent::new(EpollFlags::EPOLLIN, CONTROL),
+                )
+                .map_err(|_| Errors::Epoll)?;
+            event_loop.control = Some(server);
+        }
+
+        if let Some(stop) = stop {
+            event_loop
+                .epoll
+                .add(stop.as_fd(), EpollEvent::new(EpollFlags::EPOLLIN, STOP))
+                .map_err(|_| Errors::Epoll)?;
+            event_loop.stop = Some(stop);
+        }
+
+        if let Some(path) = watch {
+            let inotify = watch_config(&path)?;
+            event_loop
+                .epoll
+                .add(
+                    inotify.0.as_fd(),
+                    EpollEvent::new(EpollFlags::EPOLLIN, WATCH),
+                )
+                .map_err(|_| Errors::Epoll)?;
+            event_loop.watch = Some(inotify);
+        }
+
+        for device in device::scan()? {
+            event_loop.add_device(device);
+        }
+        event_loop.notify("READY=1");
+        event_loop.notify_status();
+
+        Ok(event_loop)
+    }
+
+    pub fn run(&mut self) -> Result<(), Errors> {
+        let mut events = [EpollEvent::empty(); 16];
+        loop {
+            let timeout = self.timeout();
+            let count = match self.epoll.wait(&mut events, timeout) {
+                Ok(count) => count,
+                Err(Errno::EINTR) => 0,
+                Err(_) => return Err(Errors::Epoll),
+            };
+
+            let mut fired = Vec::new();
+            for event in &events[..count] {
+                match event.data() {
+                    MONITOR => self.handle_udev(),
+                    SIGNALS => self.handle_signals(),
+                    CONTROL => self.handle_control(),
+                    WATCH => self.handle_watch(),
+                    STOP => self.stopping = true,
+                    CHILDREN => self.reap(),
+                    fd if self
+                        .control
+                        .as_ref()
+                        .is_some_and(|control| control.owns(fd as RawFd)) =>
+                    {
+                        self.handle_client(fd as RawFd)
+                    }
+                    fd if self.registry.passthrough_of(fd as RawFd).is_some() => {
+                        self.handle_passthrough(fd as RawFd)
+                    }
+                    fd => fired.extend(self.handle_device(fd as RawFd)),
+                }
+            }
+
+            let now = SystemTime::now();
+            let generation = self.generation;
+            for (_, entry) in self.registry.iter_mut() {
+                let controller = &entry.controller;
+                fired.extend(
+                    entry
+                        .gestures
+                        .tick(now)
+                        .into_iter()
+                        .map(|i| (controller.clone(), i, now, generation)),
+                );
+            }
+
+            if self.stopping {
+                info!("Stopping");
+                self.notify("STOPPING=1");
+                self.supervisor.shutdown();
+                return Ok(());
+            }
+            if self.paused {
+                fired.clear();
+            }
+            // A reload in the same batch renumbered the bindings, drop what fired before it.
+            for (controller, i, time, generation) in fired {
+                if generation == self.generation {
+                    self.fire(controller, i, time);
+                }
+            }
+            self.supervisor.tick(Instant::now());
+            if let Some(notifier) = &mut self.notifier {
+                notifier.tick(Instant::now());
+            }
+        }
+    }
+
+    fn notify(&self, state: &str) {
+        if let Some(notifier) = &self.notifier {
+            notifier.notify(state);
+        }
+    }
+
+    fn notify_status(&self) {
+        let count = self.registry.iter().count();
+        let status = match count {
+            1 => "STATUS=Listening to 1 controller".to_string(),
+            _ => format!("STATUS=Listening to {count} controllers"),
+        };
+        self.notify(&status);
+    }
+
+    /// Reports a fired binding and runs its action.
+    fn fire(&mut self, controller: Arc<Controller>, binding: usize, time: SystemTime) {
+        let event = ButtonEvent {
+            controller,
+            binding,
+            button: self.bindings[binding].button.clone(),
+            axis: self.bindings[binding].axis,
+            kind: PressKind::of(&self.bindings[binding]),
+            time,
+        };
+        info!(
+            device:% = event.controller.name,
+            devnode:% = event.controller.devnode,
+            button:% = event.input(),
+            kind:% = event.kind,
+            binding = binding;
+            "Binding fired"
+        );
+        for handler in &mut self.handlers.event {
+            handler(&event);
+        }
+
+        let Some(action) = &self.actions[binding] else {
+            return;
+        };
+        let mut ctx = Context {
+            event: &event,
+            binding: &self.bindings[binding],
+            supervisor: &mut self.supervisor,
+            run_id: self.run_ids[binding],
+            emit: &mut self.handlers.emit,
+        };
+        if let Err(e) = action.run(&mut ctx) {
+            self.report(&e);
+        }
+    }
+
+    fn report(&mut self, e: &Errors) {
+        error!("{e}");
+        for handler in &mut self.handlers.error {
+            handler(e);
+        }
+    }
+
+    /// Swaps in the bindings from the reload handler, keeping the open devices.
+    fn reload(&mut self) -> Result<(), Errors> {
+        let Some(reload) = &mut self.reload else {
+            return Err(Errors::Control(
+                "Nothing to reload, the bindings didn't come from a config file.".to_string(),
+            ));
+        };
+        let mut bindings = reload()?;
+        let mut actions = bindings
+            .iter()
+            .map(|binding| self.factories.build(binding))
+            .collect::<Result<Vec<_>, Errors>>()?;
+        self.factories.open_devices()?;
+
+        // Bindings that are still there keep their children, whatever their new index.
+        let kept = self.bindings.len() - self.bound;
+        let mut old: Vec<Option<usize>> = self.run_ids[..kept].iter().copied().map(Some).collect();
+        let mut run_ids: Vec<usize> = bindings
+            .iter()
+            .map(|binding| {
+                self.bindings[..kept]
+                    .iter()
+                    .zip(&mut old)
+                    .find(|(previous, id)| id.is_some() && previous.same_as(binding))
+                    .and_then(|(_, id)| id.take())
+                    .unwrap_or_else(|| {
+                        self.next_run_id += 1;
+                        self.next_run_id - 1
+                    })
+            })
+            .collect();
+        run_ids.extend_from_slice(&self.run_ids[kept..]);
+
+        bindings.extend_from_slice(&self.bindings[kept..]);
+        actions.extend(self.actions.drain(kept..));
+        self.bindings = Arc::new(bindings);
+        self.actions = actions;
+        self.run_ids = run_ids;
+        self.generation += 1;
+
+        let now = SystemTime::now();
+        for (_, entry) in self.registry.iter_mut() {
+            let pressed = entry.device.get_key_state().unwrap_or_default();
+            entry.gestures = Gestures::new(
+                self.bindings.clone(),
+                pressed.iter(),
+                axes(&entry.device),
+                now,
+            );
+        }
+        info!(bindings = self.bindings.len(); "Reloaded bindings");
+        Ok(())
+    }
+
+    /// Wakes up in time for the earliest pending hold, multi-tap, kill or watchdog ping,
+    /// otherwise waits for events.
+    fn timeout(&self) -> EpollTimeout {
+        let now = SystemTime::now();
+        let gestures = self
+            .registry
+            .iter()
+            .filter_map(|(_, entry)| entry.gestures.next_deadline())
+            .map(|deadline| deadline.duration_since(now).unwrap_or_default());
+        let timers = self
+            .supervisor
+            .next_deadline()
+            .into_iter()
+            .chain(self.notifier.as_ref().and_then(Notifier::next_deadline))
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+
+        gestures
+            .chain(timers)
+            .min()
+            .map(|wait| {
+                EpollTimeout::try_from(wait + Duration::from_millis(1)).unwrap_or(EpollTimeout::MAX)
+            })
+            .unwrap_or(EpollTimeout::NONE)
+    }
+
+    fn handle_signals(&mut self) {
+        let mut hangup = false;
+        while let Ok(Some(signal)) = self.signals.read_signal() {
+            let signal = signal.ssi_signo as i32;
+            hangup |= signal == Signal::SIGHUP as i32;
+            self.stopping |= signal == Signal::SIGINT as i32 || signal == Signal::SIGTERM as i32;
+        }
+        if hangup
+            && !self.stopping
+            && let Err(e) = self.reload()
+        {
+            self.report(&e);
+        }
+
+        self.reap();
+    }
+
+    /// Collects the children that exited, starting the commands queued behind them.
+    fn reap(&mut self) {
+        for e in self.supervisor.reap() {
+            self.report(&e);
+        }
+    }
+
+    /// Reloads once the watched config was written or replaced.
+    fn handle_watch(&mut self) {
+        let Some((inotify, name)) = &self.watch else {
+            return;
+        };
+        let mut changed = false;
+        while let Ok(events) = inotify.read_events() {
+            changed |= events.iter().any(|event| event.name.as_ref() == Some(name));
+        }
+        if changed && let Err(e) = self.reload() {
+            self.report(&e);
+        }
+    }
+
+    fn handle_control(&mut self) {
+        let Some(control) = &mut self.control else {
+            return;
+        };
+        for stream in control.accept() {
+            let token = stream.as_raw_fd() as u64;
+            let _ = self
+                .epoll
+                .add(stream, EpollEvent::new(EpollFlags::EPOLLIN, token));
+        }
+    }
+
+    /// Answers the requests a control client sent.
+    fn handle_client(&mut self, fd: RawFd) {
+        let Some(requests) = self.control.as_mut().and_then(|control| control.read(fd)) else {
+            self.close_client(fd);
+            return;
+        };
+        for request in requests {
+            let response = match request {
+                Ok(request) => {
+                    debug!(request:?; "Control request");
+                    self.answer(request)
+                }
+                Err(e) => Response::error(format!("Invalid request: {e}")),
+            };
+            let sent = self
+                .control
+                .as_mut()
+                .is_some_and(|control| control.reply(fd, &response));
+            if !sent {
+                self.close_client(fd);
+                return;
+            }
+        }
+    }
+
+    fn close_client(&mut self, fd: RawFd) {
+        if let Some(stream) = self.control.as_mut().and_then(|control| control.close(fd)) {
+            let _ = self.epoll.delete(&stream);
+        }
+    }
+
+    fn answer(&mut self, request: Request) -> Response {
+        match request {
+            Request::List => {
+                let mut controllers: Vec<Controller> = self
+                    .registry
+                    .iter()
+                    .map(|(_, entry)| (*entry.controller).clone())
+                    .collect();
+                controllers.sort_by(|a, b| a.devnode.cmp(&b.devnode));
+                Response {
+                    controllers: Some(controllers),
+                    ..Response::ok()
+                }
+            }
+            Request::Bindings => Response {
+                bindings: Some(self.binding_infos()),
+                ..Response::ok()
+            },
+            Request::Trigger { name } => {
+                let Some(i) = self
+                    .bindings
+                    .iter()
+                    .position(|binding| binding.name.as_ref() == Some(&name))
+                else {
+                    return Response::error(format!("No binding named '{name}'."));
+                };
+                self.fire(Arc::new(Controller::control_socket()), i, SystemTime::now());
+                Response::ok()
+            }
+            Request::Pause | Request::Resume => {
+                self.paused = request == Request::Pause;
+                info!("{}", if self.paused { "Paused" } else { "Resumed" });
+                Response {
+                    paused: Some(self.paused),
+                    ..Response::ok()
+                }
+            }
+            Request::Reload => match self.reload() {
+                Ok(()) => Response::ok(),
+                Err(e) => {
+                    self.report(&e);
+                    Response::error(e.to_string())
+                }
+            },
+        }
+    }
+
+    fn binding_infos(&self) -> Vec<BindingInfo> {
+        self.bindings
+            .iter()
+            .zip(&self.actions)
+            .enumerate()
+            .map(|(index, (binding, action))| BindingInfo {
+                index,
+                name: binding.name.clone(),
+                button: binding.input(),
+                kind: PressKind::of(binding).to_string(),
+                action: match binding.action_options() {
+                    Some(options) => options
+                        .get("type")
+                        .and_then(|v| v.as_str())
+                        .map(str::to_string),
+                    None => action.as_ref().map(|_| "custom".to_string()),
+                },
+            })
+            .collect()
+    }
+
+    fn handle_udev(&mut self) {
+        let events: Vec<udev::Event> = self.monitor.iter().collect();
+        for event in events {
+            match event.event_type() {
+                udev::EventType::Add => self.add_device(event.device()),
+                udev::EventType::Remove => {
+                    if let Some((_, entry)) = self.registry.remove(&event.device()) {
+                        self.forget(entry);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Reads whatever the device has queued, returning the bindings it triggered.
+    fn handle_device(&mut self, fd: RawFd) -> Vec<Fired> {
+        let Some(devnode) = self.registry.devnode_of(fd) else {
+            return Vec::new();
+        };
+        let Some(entry) = self.registry.get_mut(&devnode) else {
+            return Vec::new();
+        };
+
+        let mut fired = Vec::new();
+        // What goes to the passthrough copy on the next SYN_REPORT, bound buttons aside.
+        let mut frame = Vec::new();
+        let paused = self.paused;
+        let generation = self.generation;
+        let fetched = match entry.device.fetch_events() {
+            Ok(events) => {
+                for event in events {
+                    for handler in &mut self.handlers.input {
+                        handler(&entry.controller, &event);
+                    }
+                    let time = event.timestamp();
+                    let triggered = match event.destructure() {
+                        EventSummary::Key(_, key, value) => {
+                            trace!(devnode:% = devnode, key:? = key, value = value; "Key");
+                            let consumed = !paused && entry.gestures.consumes(key);
+                            if let Some(passthrough) = &mut entry.passthrough
+                                && passthrough.passes_key(key, value, consumed)
+                            {
+                                frame.push(event);
+                            }
+                            entry.gestures.key(key, value, time)
+                        }
+                        EventSummary::AbsoluteAxis(_, axis, value) => {
+                            frame.push(event);
+                            entry.gestures.axis(axis, value)
+                        }
+                        EventSummary::Synchronization(_, SynchronizationCode::SYN_REPORT, _) => {
+                            if let Some(passthrough) = &mut entry.passthrough
+                                && !frame.is_empty()
+                                && let Err(e) = passthrough.emit(&frame)
+                            {
+                                warn!(devnode:% = devnode; "Failed to pass events on: {e}");
+                            }
+                            frame.clear();
+                            continue;
+                        }
+                        EventSummary::Synchronization(..) => continue,
+                        _ => {
+                            frame.push(event);
+                            continue;
+                        }
+                    };
+                    fired.extend(
+                        triggered
+                            .into_iter()
+                            .map(|i| (entry.controller.clone(), i, time, generation)),
+                    );
+                }
+                true
+            }
+            Err(e) => e.kind() == io::ErrorKind::WouldBlock,
+        };
+
+        if !fetched {
+            // Gone before udev told us, forget it so a replug can open it again.
+            if let Some(entry) = self.registry.remove_devnode(&devnode) {
+                self.forget(entry);
+            }
+            return Vec::new();
+        }
+        fired
+    }
+
+    /// Plays the rumble that games sent to the passthrough copy behind `fd` on its controller.
+    fn handle_passthrough(&mut self, fd: RawFd) {
+        let Some(devnode) = self.registry.passthrough_of(fd) else {
+            return;
+        };
+        let Some(entry) = self.registry.get_mut(&devnode) else {
+            return;
+        };
+        if let Some(passthrough) = &mut entry.passthrough
+            && let Err(e) = passthrough.forward_feedback(&mut entry.device)
+        {
+            warn!(devnode:% = devnode; "Failed to pass rumble on: {e}");
+        }
+    }
+
+    fn forget(&mut self, entry: Entry) {
+        let _ = self.epoll.delete(entry.device.as_fd());
+        if let Some(passthrough) = &entry.passthrough {
+            let _ = self.epoll.delete(passthrough);
+        }
+        info!(
+            device:% = entry.controller.name,
+            devnode:% = entry.controller.devnode;
+            "Controller disconnected"
+        );
+        self.notify_status();
+        for handler in &mut self.handlers.disconnect {
+            handler(&entry.controller);
+        }
+    }
+
+    /// Opens `device` if it's a controller that isn't open yet, logging why not otherwise.
+    fn add_device(&mut self, device: udev::Device) {
+        let syspath = device.syspath().to_path_buf();
+        match self.verify_device(device) {
+            Ok(()) => {}
+            Err(
+                e @ (Errors::NotController
+                | Errors::NotEventDevice
+                | Errors::VirtualDevice
+                | Errors::NoDevicePath
+                | Errors::AlreadyListening),
+            ) => trace!(syspath:% = syspath.display(); "Skipping device: {e}"),
+            Err(e) => warn!(syspath:% = syspath.display(); "{e}"),
+        }
+    }
+
+    fn verify_device(&mut self, device: udev::Device) -> Result<(), Errors> {
+        let devnode = device::controller_devnode(&device)?;
+        if self.registry.contains(&devnode) {
+            return Err(Errors::AlreadyListening);
+        }
+        let mut evdev_device = evdev::Device::open(&devnode).map_err(|_| Errors::EvdevOpen)?;
+
+        evdev_device
+            .set_nonblocking(true)
+            .map_err(|_| Errors::EvdevOpen)?;
+        let pressed = evdev_device
+            .get_key_state()
+            .map_err(|_| Errors::EvdevOpen)?;
+        let token = evdev_device.as_raw_fd() as u64;
+        self.epoll
+            .add(
+                evdev_device.as_fd(),
+                EpollEvent::new(EpollFlags::EPOLLIN, token),
+            )
+            .map_err(|_| Errors::Epoll)?;
+        let passthrough = if self.grab {
+            pass_through(&mut evdev_device, &devnode)
+        } else {
+            None
+        };
+        if let Some(passthrough) = &passthrough {
+            let token = passthrough.as_fd().as_raw_fd() as u64;
+            self.epoll
+                .add(passthrough, EpollEvent::new(EpollFlags::EPOLLIN, token))
+                .map_err(|_| Errors::Epoll)?;
+        }
+
+        let controller = Arc::new(Controller::new(devnode.clone(), &device, &evdev_device));
+        info!(
+            device:% = controller.name,
+            devnode:% = devnode,
+            vendor:% = format_args!("{:04x}", controller.vendor_id),
+            product:% = format_args!("{:04x}", controller.product_id);
+            "Controller connected"
+        );
+        for handler in &mut self.handlers.connect {
+            handler(&controller);
+        }
+        let entry = Entry {
+            controller,
+            gestures: Gestures::new(
+                self.bindings.clone(),
+                pressed.iter(),
+                axes(&evdev_device),
+                SystemTime::now(),
+            ),
+            device: evdev_device,
+            passthrough,
+        };
+        self.registry.insert(devnode, entry);
+        self.notify_status();
+
+        Ok(())
+    }
+}
+
+/// The caller's signal mask, set back when dropped.
+struct RestoreMask(SigSet);
+
+impl Drop for RestoreMask {
+    fn drop(&mut self) {
+        let _ = self.0.thread_set_mask();
+    }
+}
+
+/// Grabs `device` behind a copy that gets its events, `None` leaving it ungrabbed
+/// if it can't be copied.
+fn pass_through(device: &mut evdev::Device, devnode: &str) -> Option<Passthrough> {
+    let passthrough = Passthrough::clone_of(device).and_then(|passthrough| {
+        device.grab()?;
+        Ok(passthrough)
+    });
+    match passthrough {
+        Ok(passthrough) => {
+            debug!(devnode:% = devnode; "Grabbed the controller");
+            Some(passthrough)
+        }
+        Err(e) => {
+            warn!(devnode:% = devnode; "Not grabbing the controller, it can't be passed through: {e}");
+            None
+        }
+    }
+}
+
+/// The axes of `device` with their range and current position.
+fn axes(device: &evdev::Device) -> Vec<(AbsoluteAxisCode, AbsInfo)> {
+    device
+        .get_absinfo()
+        .map(|axes| axes.collect())
+        .unwrap_or_default()
+}
+
+/// Watches the directory of `path`, editors tend to replace a file rather than write to it.
+fn watch_config(path: &Path) -> Result<(Inotify, OsString), Errors> {
+    let error = |e: String| Errors::ConfigWatch(path.display().to_string(), e);
+    let name = path
+        .file_name()
+        .ok_or_else(|| error("not a file".to_string()))?
+        .to_os_string();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)
+        .map_err(|e| error(e.to_string()))?;
+    inotify
+        .add_watch(
+            dir,
+            AddWatchFlags::IN_CLOSE_WRITE | AddWatchFlags::IN_MOVED_TO,
+        )
+        .map_err(|e| error(e.to_string()))?;
+    Ok((inotify, name))
+}
@@ -0,0 +1,561 @@
+use evdev::{AbsInfo, AbsoluteAxisCode, KeyCode};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use crate::config::{Binding, Trigger};
+
+/// Turns the raw key events of one device into the bindings they trigger.
+///
+/// Everything is timed with the evdev event timestamps, `tick` only exists so a
+/// `hold` binding can fire while the button is still down, and so a pending
+/// multi-tap resolves once its window runs out.
+///
+/// Every key that is down is tracked, so chords can match against the whole set.
+/// Once a chord fires its buttons are used up: letting go of them, or holding
+/// them, doesn't trigger their single-button bindings anymore.
+///
+/// Axis bindings keep whether their threshold is crossed, so they fire once per crossing.
+pub struct Gestures {
+    bindings: Arc<Vec<Binding>>,
+    held: HashMap<KeyCode, Held>,
+    taps: HashMap<KeyCode, Taps>,
+    /// Range of every axis of the device.
+    ranges: HashMap<AbsoluteAxisCode, Range>,
+    /// State of the axis bindings, by index.
+    crossings: HashMap<usize, Crossing>,
+}
+
+struct Held {
+    since: SystemTime,
+    /// Indices of the `hold` bindings that already fired during this press.
+    holds_fired: Vec<usize>,
+    /// Part of a chord that fired, or already down when the device was opened.
+    consumed: bool,
+}
+
+/// Sticks and d-pad hats, which go both ways whatever their range.
+const TWO_WAY_AXES: [AbsoluteAxisCode; 12] = [
+    AbsoluteAxisCode::ABS_X,
+    AbsoluteAxisCode::ABS_Y,
+    AbsoluteAxisCode::ABS_RX,
+    AbsoluteAxisCode::ABS_RY,
+    AbsoluteAxisCode::ABS_HAT0X,
+    AbsoluteAxisCode::ABS_HAT0Y,
+    AbsoluteAxisCode::ABS_HAT1X,
+    AbsoluteAxisCode::ABS_HAT1Y,
+    AbsoluteAxisCode::ABS_HAT2X,
+    AbsoluteAxisCode::ABS_HAT2Y,
+    AbsoluteAxisCode::ABS_HAT3X,
+    AbsoluteAxisCode::ABS_HAT3Y,
+];
+
+/// How the raw values of an axis map to a position.
+struct Range {
+    min: f32,
+    max: f32,
+    /// Middle of the range for axes that go both ways, `None` for those that don't.
+    centre: Option<f32>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Crossing {
+    Within,
+    Crossed,
+    /// Already past the threshold when the device was opened, ignored until it comes back.
+    Stale,
+}
+
+/// Releases counted so far while a binding with more `taps` could still match.
+struct Taps {
+    count: u8,
+    deadline: SystemTime,
+}
+
+impl Gestures {
+    /// `pressed` are the keys that are already down, see `evdev::Device::get_key_state`,
+    /// and `axes` the device's axes with their range and position, see `evdev::Device::get_absinfo`.
+    pub fn new(
+        bindings: Arc<Vec<Binding>>,
+        pressed: impl IntoIterator<Item = KeyCode>,
+        axes: impl IntoIterator<Item = (AbsoluteAxisCode, AbsInfo)>,
+        now: SystemTime,
+    ) -> Gestures {
+        let held = pressed
+            .into_iter()
+            .map(|key| {
+                let held = Held {
+                    since: now,
+                    holds_fired: Vec::new(),
+                    consumed: true,
+                };
+                (key, held)
+            })
+            .collect();
+        let axes: HashMap<AbsoluteAxisCode, AbsInfo> = axes.into_iter().collect();
+        let ranges: HashMap<AbsoluteAxisCode, Range> = axes
+            .iter()
+            .map(|(&axis, info)| (axis, Range::of(axis, info)))
+            .collect();
+        let crossings = bindings
+            .iter()
+            .enumerate()
+            .filter_map(|(i, binding)| {
+                let axis = binding.axis?.0;
+                let position = ranges.get(&axis)?.position(axes[&axis].value());
+                let crossing = if crossed(binding, position) {
+                    Crossing::Stale
+                } else {
+                    Crossing::Within
+                };
+                Some((i, crossing))
+            })
+            .collect();
+        Gestures {
+            bindings,
+            held,
+            taps: HashMap::new(),
+            ranges,
+            crossings,
+        }
+    }
+
+    /// Feeds one `EV_ABS` event, returning the indices of the bindings to run.
+    pub fn axis(&mut self, axis: AbsoluteAxisCode, value: i32) -> Vec<usize> {
+        let Some(range) = self.ranges.get(&axis) else {
+            return Vec::new();
+        };
+        let position = range.position(value);
+
+        let mut fired = Vec::new();
+        for (&i, crossing) in self.crossings.iter_mut() {
+            let binding = &self.bindings[i];
+            if binding.axis.map(|a| a.0) != Some(axis) {
+                continue;
+            }
+            match crossing {
+                Crossing::Within if crossed(binding, position) => {
+                    *crossing = Crossing::Crossed;
+                    if binding.trigger == Trigger::Press {
+                        fired.push(i);
+                    }
+                }
+                Crossing::Crossed | Crossing::Stale if came_back(binding, position) => {
+                    if *crossing == Crossing::Crossed && binding.trigger == Trigger::Release {
+                        fired.push(i);
+                    }
+                    *crossing = Crossing::Within;
+                }
+                _ => {}
+            }
+        }
+        fired.sort_unstable();
+        fired
+    }
+
+    /// Feeds one `EV_KEY` event, returning the indices of the bindings to run.
+    pub fn key(&mut self, key: KeyCode, value: i32, time: SystemTime) -> Vec<usize> {
+        match value {
+            1 => {
+                let mut fired = self.expire_taps(key, time);
+                self.held.insert(
+                    key,
+                    Held {
+                        since: time,
+                        holds_fired: Vec::new(),
+                        consumed: false,
+                    },
+                );
+                fired.extend(self.matching(key, Trigger::Press));
+                fired.extend(self.chords(key));
+                fired
+            }
+            0 => {
+                let Some(mut held) = self.held.remove(&key) else {
+                    return Vec::new();
+                };
+                if held.consumed {
+                    return Vec::new();
+                }
+                // The release can arrive before the next tick noticed the hold.
+                let mut fired = self.due_holds(key, &held, time);
+                held.holds_fired.extend(&fired);
+                if held.holds_fired.is_empty() {
+                    fired.extend(self.tap(key, time));
+                } else {
+                    self.taps.remove(&key);
+                }
+                fired
+            }
+            _ => Vec::new(), // autorepeat
+        }
+    }
+
+    /// Fires the `hold` bindings whose threshold has passed by `now`, and the
+    /// multi-tap sequences whose window closed.
+    pub fn tick(&mut self, now: SystemTime) -> Vec<usize> {
+        let mut fired = Vec::new();
+        let keys: Vec<KeyCode> = self.held.keys().copied().collect();
+        for key in keys {
+            let due = self.due_holds(key, &self.held[&key], now);
+            if let Some(held) = self.held.get_mut(&key) {
+                held.holds_fired.extend(&due);
+            }
+            if !due.is_empty() {
+                self.taps.remove(&key);
+            }
+            fired.extend(due);
+        }
+
+        let keys: Vec<KeyCode> = self.taps.keys().copied().collect();
+        for key in keys {
+            if !self.held.contains_key(&key) {
+                fired.extend(self.expire_taps(key, now));
+            }
+        }
+        fired
+    }
+
+    /// Whether `key` has a binding of its own, chords don't count.
+    pub fn consumes(&self, key: KeyCode) -> bool {
+        self.bindings
+            .iter()
+            .any(|b| !b.is_chord() && b.button.contains(key))
+    }
+
+    /// When `tick` should be called next, if anything is pending.
+    pub fn next_deadline(&self) -> Option<SystemTime> {
+        let holds = self
+            .held
+            .iter()
+            .filter(|(_, held)| !held.consumed)
+            .flat_map(|(&key, held)| {
+                self.matching(key, Trigger::Hold)
+                    .filter(|i| !held.holds_fired.contains(i))
+                    .map(|i| held.since + self.bindings[i].hold_duration())
+            });
+        let taps = self
+            .taps
+            .iter()
+            .filter(|(key, _)| !self.held.contains_key(key))
+            .map(|(_, taps)| taps.deadline);
+        holds.chain(taps).min()
+    }
+
+    /// Fires the chords that `key` just completed, using up all of their buttons.
+    fn chords(&mut self, key: KeyCode) -> Vec<usize> {
+        let fired: Vec<usize> = self
+            .bindings
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.is_chord() && b.button.contains(key))
+            .filter(|(_, b)| {
+                b.button
+                    .0
+                    .iter()
+                    .all(|button| self.held.contains_key(&button.0))
+            })
+            .map(|(i, _)| i)
+            .collect();
+
+        for &i in &fired {
+            for button in &self.bindings[i].button.0 {
+                if let Some(held) = self.held.get_mut(&button.0) {
+                    held.consumed = true;
+                }
+                self.taps.remove(&button.0);
+            }
+        }
+        fired
+    }
+
+    /// Counts a short press, firing right away unless a binding with more taps could still match.
+    fn tap(&mut self, key: KeyCode, time: SystemTime) -> Vec<usize> {
+        let count = self.taps.remove(&key).map_or(0, |t| t.count) + 1;
+        let window = self
+            .matching(key, Trigger::Release)
+            .filter(|&i| self.bindings[i].taps > count)
+            .map(|i| self.bindings[i].multi_tap_window())
+            .max();
+
+        match window {
+            Some(window) => {
+                self.taps.insert(
+                    key,
+                    Taps {
+                        count,
+                        deadline: time + window,
+                    },
+                );
+                Vec::new()
+            }
+            None => self.tapped(key, count),
+        }
+    }
+
+    /// Resolves the pending taps on `key` if its window closed before `now`.
+    fn expire_taps(&mut self, key: KeyCode, now: SystemTime) -> Vec<usize> {
+        match self.taps.get(&key) {
+            Some(taps) if taps.deadline <= now => {
+                let count = taps.count;
+                self.taps.remove(&key);
+                self.tapped(key, count)
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn tapped(&self, key: KeyCode, count: u8) -> Vec<usize> {
+        self.matching(key, Trigger::Release)
+            .filter(|&i| self.bindings[i].taps == count)
+            .collect()
+    }
+
+    fn due_holds(&self, key: KeyCode, held: &Held, now: SystemTime) -> Vec<usize> {
+        if held.consumed {
+            return Vec::new();
+        }
+        let elapsed = now.duration_since(held.since).unwrap_or(Duration::ZERO);
+        self.matching(key, Trigger::Hold)
+            .filter(|&i| !held.holds_fired.contains(&i))
+            .filter(|&i| elapsed >= self.bindings[i].hold_duration())
+            .collect()
+    }
+
+    fn matching(&self, key: KeyCode, trigger: Trigger) -> impl Iterator<Item = usize> + '_ {
+        self.bindings
+            .iter()
+            .enumerate()
+            .filter(move |(_, b)| !b.is_chord() && b.button.contains(key) && b.trigger == trigger)
+            .map(|(i, _)| i)
+    }
+}
+
+impl Range {
+    /// Sticks, hats, axes that go below 0 and those with a dead zone around their middle
+    /// go both ways, so a DualSense's 0..255 sticks are centred on 127.5 like an Xbox pad's
+    /// are on 0. Only the range decides, not where the axis happens to be when it's opened.
+    fn of(axis: AbsoluteAxisCode, info: &AbsInfo) -> Range {
+        let (min, max) = (info.minimum() as f32, info.maximum() as f32);
+        let two_way = TWO_WAY_AXES.contains(&axis) || min < 0.0 || info.flat() > 0;
+        Range {
+            min,
+            max,
+            centre: two_way.then_some((min + max) / 2.0),
+        }
+    }
+
+    /// Where `value` sits: -1..1 for axes that go both ways, each side scaled against
+    /// its own half of the range, 0..1 for those that don't.
+    fn position(&self, value: i32) -> f32 {
+        let value = value as f32;
+        let position = match self.centre {
+            Some(centre) if value < centre => (value - centre) / (centre - self.min),
+            Some(centre) => (value - centre) / (self.max - centre),
+            None => (value - self.min) / (self.max - self.min),
+        };
+        if position.is_finite() {
+            position.clamp(-1.0, 1.0)
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Whether `position` is past the binding's threshold, above a positive one or below a negative one.
+fn crossed(binding: &Binding, position: f32) -> bool {
+    match binding.threshold.unwrap_or(1.0) {
+        threshold if threshold > 0.0 => position >= threshold,
+        threshold => position <= threshold,
+    }
+}
+
+/// Whether `position` is back within the threshold, past the hysteresis band.
+fn came_back(binding: &Binding, position: f32) -> bool {
+    match binding.threshold.unwrap_or(1.0) {
+        threshold if threshold > 0.0 => position < threshold - binding.hysteresis(),
+        threshold => position > threshold + binding.hysteresis(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Axis, Button};
+
+    const MODE: KeyCode = KeyCode::BTN_MODE;
+    const START: KeyCode = KeyCode::BTN_START;
+
+    fn at(ms: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_000) + Duration::from_millis(ms)
+    }
+
+    fn binding(key: KeyCode, trigger: Trigger) -> Binding {
+        Binding {
+            hold_ms: Some(800),
+            multi_tap_ms: Some(300),
+            ..Binding::new(Button(key), trigger)
+        }
+    }
+
+    fn taps(key: KeyCode, taps: u8) -> Binding {
+        Binding {
+            taps,
+            ..binding(key, Trigger::Release)
+        }
+    }
+
+    fn gestures(bindings: Vec<Binding>) -> Gestures {
+        Gestures::new(Arc::new(bindings), [], [], at(0))
+    }
+
+    #[test]
+    fn single_tap_fires_on_release() {
+        let mut g = gestures(vec![binding(MODE, Trigger::Release)]);
+        assert!(g.key(MODE, 1, at(0)).is_empty());
+        assert_eq!(g.key(MODE, 0, at(50)), [0]);
+    }
+
+    #[test]
+    fn press_fires_right_away() {
+        let mut g = gestures(vec![binding(MODE, Trigger::Press)]);
+        assert_eq!(g.key(MODE, 1, at(0)), [0]);
+        assert!(g.key(MODE, 2, at(10)).is_empty());
+        assert!(g.key(MODE, 0, at(50)).is_empty());
+    }
+
+    #[test]
+    fn double_tap_wins_over_single_tap() {
+        let mut g = gestures(vec![taps(MODE, 1), taps(MODE, 2)]);
+        g.key(MODE, 1, at(0));
+        assert!(g.key(MODE, 0, at(50)).is_empty());
+        assert!(g.tick(at(200)).is_empty());
+        g.key(MODE, 1, at(250));
+        assert_eq!(g.key(MODE, 0, at(300)), [1]);
+        assert!(g.tick(at(1_000)).is_empty());
+    }
+
+    #[test]
+    fn single_tap_fires_once_the_window_closes() {
+        let mut g = gestures(vec![taps(MODE, 1), taps(MODE, 2)]);
+        g.key(MODE, 1, at(0));
+        g.key(MODE, 0, at(50));
+        assert_eq!(g.next_deadline(), Some(at(350)));
+        assert!(g.tick(at(349)).is_empty());
+        assert_eq!(g.tick(at(350)), [0]);
+        assert_eq!(g.next_deadline(), None);
+    }
+
+    #[test]
+    fn late_tap_starts_over() {
+        let mut g = gestures(vec![taps(MODE, 1), taps(MODE, 2)]);
+        g.key(MODE, 1, at(0));
+        g.key(MODE, 0, at(50));
+        // No tick in between, the next press resolves the first tap.
+        assert_eq!(g.key(MODE, 1, at(500)), [0]);
+        assert!(g.key(MODE, 0, at(550)).is_empty());
+    }
+
+    #[test]
+    fn hold_suppresses_release() {
+        let mut g = gestures(vec![
+            binding(MODE, Trigger::Release),
+            binding(MODE, Trigger::Hold),
+        ]);
+        g.key(MODE, 1, at(0));
+        assert_eq!(g.next_deadline(), Some(at(800)));
+        assert!(g.tick(at(500)).is_empty());
+        assert_eq!(g.tick(at(800)), [1]);
+        assert!(g.tick(at(900)).is_empty());
+        assert!(g.key(MODE, 0, at(1_000)).is_empty());
+    }
+
+    #[test]
+    fn hold_due_at_release_fires_instead_of_release() {
+        let mut g = gestures(vec![
+            binding(MODE, Trigger::Release),
+            binding(MODE, Trigger::Hold),
+        ]);
+        g.key(MODE, 1, at(0));
+        assert_eq!(g.key(MODE, 0, at(800)), [1]);
+    }
+
+    #[test]
+    fn short_press_releases_despite_hold() {
+        let mut g = gestures(vec![
+            binding(MODE, Trigger::Release),
+            binding(MODE, Trigger::Hold),
+        ]);
+        g.key(MODE, 1, at(0));
+        assert_eq!(g.key(MODE, 0, at(799)), [0]);
+    }
+
+    #[test]
+    fn chord_consumes_its_buttons() {
+        let mut g = gestures(vec![
+            binding(MODE, Trigger::Release),
+            binding(MODE, Trigger::Hold),
+            binding(START, Trigger::Release),
+            Binding::chord(vec![Button(MODE), Button(START)]),
+        ]);
+        assert!(g.key(MODE, 1, at(0)).is_empty());
+        assert_eq!(g.key(START, 1, at(10)), [3]);
+        assert!(g.tick(at(2_000)).is_empty());
+        assert!(g.key(START, 0, at(2_100)).is_empty());
+        assert!(g.key(MODE, 0, at(2_200)).is_empty());
+        // Used up for that press only.
+        g.key(START, 1, at(3_000));
+        assert_eq!(g.key(START, 0, at(3_050)), [2]);
+    }
+
+    #[test]
+    fn keys_down_at_open_are_ignored_until_pressed_again() {
+        let bindings = Arc::new(vec![binding(MODE, Trigger::Release)]);
+        let mut g = Gestures::new(bindings, [MODE], [], at(0));
+        assert!(g.key(MODE, 0, at(50)).is_empty());
+        g.key(MODE, 1, at(100));
+        assert_eq!(g.key(MODE, 0, at(150)), [0]);
+    }
+
+    fn stick(value: i32) -> (AbsoluteAxisCode, AbsInfo) {
+        (
+            AbsoluteAxisCode::ABS_X,
+            AbsInfo::new(value, 0, 255, 0, 0, 0),
+        )
+    }
+
+    fn left(trigger: Trigger) -> Arc<Vec<Binding>> {
+        let axis = Axis(AbsoluteAxisCode::ABS_X);
+        Arc::new(vec![Binding::axis(axis, -0.9, trigger)])
+    }
+
+    #[test]
+    fn unsigned_stick_is_centred_on_the_middle_of_its_range() {
+        let mut g = Gestures::new(left(Trigger::Press), [], [stick(128)], at(0));
+        assert!(g.axis(AbsoluteAxisCode::ABS_X, 60).is_empty());
+        assert_eq!(g.axis(AbsoluteAxisCode::ABS_X, 10), [0]);
+        assert!(g.axis(AbsoluteAxisCode::ABS_X, 20).is_empty());
+        assert!(g.axis(AbsoluteAxisCode::ABS_X, 40).is_empty());
+        assert_eq!(g.axis(AbsoluteAxisCode::ABS_X, 10), [0]);
+    }
+
+    #[test]
+    fn trigger_goes_one_way_even_half_pulled_at_open() {
+        let axis = Axis(AbsoluteAxisCode::ABS_RZ);
+        let bindings = Arc::new(vec![Binding::axis(axis, 0.95, Trigger::Press)]);
+        let info = AbsInfo::new(128, 0, 255, 0, 0, 0);
+        let mut g = Gestures::new(bindings, [], [(axis.0, info)], at(0));
+        assert!(g.axis(axis.0, 242).is_empty());
+        assert_eq!(g.axis(axis.0, 243), [0]);
+    }
+
+    #[test]
+    fn axis_past_its_threshold_at_open_is_ignored_until_it_comes_back() {
+        let mut g = Gestures::new(left(Trigger::Release), [], [stick(5)], at(0));
+        assert!(g.axis(AbsoluteAxisCode::ABS_X, 128).is_empty());
+        assert!(g.axis(AbsoluteAxisCode::ABS_X, 5).is_empty());
+        assert_eq!(g.axis(AbsoluteAxisCode::ABS_X, 128), [0]);
+    }
+}
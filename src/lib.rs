@@ -0,0 +1,95 @@
+//! Listens for controller buttons, most notably the home button.
+//!
+//! A [`Listener`] opens every controller, follows hotplugs, runs the [`Action`]
+//! of each [`Binding`] that fires and reports it as a [`ButtonEvent`]. The
+//! `guiders` binary is a thin consumer that feeds it the bindings from its config. A running
+//! listener can be queried and driven through its [`control`] socket, and logs what it does
+//! through the `log` crate, with the device, button and binding as key-values. With the `tokio` feature,
+//! [`stream`] exposes the controllers and their buttons as async streams.
+
+pub mod action;
+pub mod config;
+pub mod control;
+mod controller;
+pub mod device;
+mod event_loop;
+mod gesture;
+mod listener;
+mod notify;
+mod registry;
+#[cfg(feature = "tokio")]
+pub mod stream;
+pub mod supervisor;
+mod uinput;
+
+pub use action::{Action, Actions};
+pub use config::{Axis, Binding, Button, Buttons, Config, Trigger};
+pub use controller::Controller;
+pub use listener::{ButtonEvent, Listener, PressKind, Stopper};
+pub use supervisor::{Concurrency, Supervisor};
+
+#[derive(Debug)]
+pub enum Errors {
+    UdevSubsystem,
+    UdevDeviceScan,
+    UdevError,
+    UdevMonitor,
+    Epoll,
+    Signals,
+
+    EvdevOpen,
+    EvdevFetch(String),
+
+    NotController,
+    NotEventDevice,
+    VirtualDevice,
+    AlreadyListening,
+    NoDevicePath,
+    InvalidParams,
+    Usage(String),
+    NoConfig(String),
+    ConfigRead(String, String),
+    ConfigParse(String, String),
+    ConfigWatch(String, String),
+    WriteFile(String, String),
+    InvalidAction(String, String),
+    Action(String),
+    ControlSocket(String, String),
+    Control(String),
+}
+
+impl std::fmt::Display for Errors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Errors::UdevSubsystem => write!(f, "Failed to filter devices by input subsystem."),
+            Errors::UdevDeviceScan => write!(f, "Failed to scan for devices."),
+            Errors::UdevError => write!(f, "Failed to udev."),
+            Errors::UdevMonitor => write!(f, "Failed to monitor for new devices."),
+            Errors::Epoll => write!(f, "Failed to wait for device events."),
+            Errors::Signals => write!(f, "Failed to set up signal handling."),
+            Errors::EvdevOpen => write!(f, "Failed to open device."),
+            Errors::EvdevFetch(e) => write!(f, "Failed to fetch device events: '{e}'."),
+            Errors::NotController => write!(f, "This device is not a controller."),
+            Errors::NotEventDevice => write!(f, "This device is not an evdev event node."),
+            Errors::VirtualDevice => write!(f, "This device was created by guiders."),
+            Errors::AlreadyListening => write!(f, "Already listening to this device."),
+            Errors::NoDevicePath => write!(f, "This device does not have a path? Wtf how?"),
+            Errors::InvalidParams => write!(f, "Invalid parameters, see 'guiders --help'."),
+            Errors::Usage(e) => write!(f, "Invalid parameters: {e}, see 'guiders --help'."),
+            Errors::NoConfig(p) => write!(
+                f,
+                "No config at '{p}'. Write one, pass '--config <path>' or a command to run, see 'guiders --help'."
+            ),
+            Errors::ConfigRead(p, e) => write!(f, "Failed to read config '{p}': '{e}'."),
+            Errors::ConfigParse(p, e) => write!(f, "Invalid config '{p}': '{e}'."),
+            Errors::ConfigWatch(p, e) => write!(f, "Failed to watch config '{p}': '{e}'."),
+            Errors::WriteFile(p, e) => write!(f, "Failed to write '{p}': '{e}'."),
+            Errors::InvalidAction(b, e) => write!(f, "Invalid action for binding on {b}: '{e}'."),
+            Errors::Action(e) => write!(f, "{e}"),
+            Errors::ControlSocket(p, e) => write!(f, "Control socket '{p}': '{e}'."),
+            Errors::Control(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for Errors {}
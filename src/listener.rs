@@ -0,0 +1,276 @@
+use evdev::InputEvent;
+use nix::sys::eventfd::{EfdFlags, EventFd};
+use std::{
+    fmt,
+    path::PathBuf,
+    sync::{Arc, mpsc::Sender},
+    time::SystemTime,
+};
+
+use crate::{
+    Errors,
+    action::{Action, Actions, EmitHandler},
+    config::{Axis, Binding, Buttons, Trigger},
+    controller::Controller,
+    event_loop::EventLoop,
+};
+
+/// A binding that fired on one of the controllers.
+#[derive(Debug, Clone)]
+pub struct ButtonEvent {
+    pub controller: Arc<Controller>,
+    /// Index of the binding in the current list: the one the [`Listener`] was built with,
+    /// or after a reload the reloaded bindings followed by those added with [`Listener::bind`].
+    pub binding: usize,
+    /// Empty for axis bindings.
+    pub button: Buttons,
+    /// The axis of axis bindings.
+    pub axis: Option<Axis>,
+    pub kind: PressKind,
+    pub time: SystemTime,
+}
+
+impl ButtonEvent {
+    /// The axis or the button(s) that fired, like [`Binding::input`].
+    pub fn input(&self) -> String {
+        match &self.axis {
+            Some(axis) => axis.to_string(),
+            None => self.button.to_string(),
+        }
+    }
+}
+
+/// Which gesture made a binding fire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PressKind {
+    Press,
+    Release,
+    Hold,
+    /// Released this many times in a row.
+    MultiTap(u8),
+    Chord,
+}
+
+impl fmt::Display for PressKind {
+    /// `press`, `release`, `hold`, `chord`, or `taps-2` for a double tap.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PressKind::Press => write!(f, "press"),
+            PressKind::Release => write!(f, "release"),
+            PressKind::Hold => write!(f, "hold"),
+            PressKind::MultiTap(taps) => write!(f, "taps-{taps}"),
+            PressKind::Chord => write!(f, "chord"),
+        }
+    }
+}
+
+impl PressKind {
+    pub fn of(binding: &Binding) -> PressKind {
+        match binding.trigger {
+            _ if binding.is_chord() => PressKind::Chord,
+            Trigger::Press => PressKind::Press,
+            Trigger::Hold => PressKind::Hold,
+            Trigger::Release if binding.taps > 1 => PressKind::MultiTap(binding.taps),
+            Trigger::Release => PressKind::Release,
+        }
+    }
+}
+
+pub(crate) type EventHandler = Box<dyn FnMut(&ButtonEvent)>;
+pub(crate) type ControllerHandler = Box<dyn FnMut(&Controller)>;
+pub(crate) type ErrorHandler = Box<dyn FnMut(&Errors)>;
+pub(crate) type InputHandler = Box<dyn FnMut(&Controller, &InputEvent)>;
+pub(crate) type ReloadHandler = Box<dyn FnMut() -> Result<Vec<Binding>, Errors>>;
+
+#[derive(Default)]
+pub(crate) struct Handlers {
+    pub event: Vec<EventHandler>,
+    pub input: Vec<InputHandler>,
+    pub emit: Vec<EmitHandler>,
+    pub connect: Vec<ControllerHandler>,
+    pub disconnect: Vec<ControllerHandler>,
+    pub error: Vec<ErrorHandler>,
+}
+
+/// Watches every controller and reports the bindings that fire on them.
+///
+/// ```no_run
+/// use guiders::{Binding, Listener, Trigger};
+///
+/// let home = Binding::new("BTN_MODE".parse().unwrap(), Trigger::Release);
+/// Listener::new(vec![home])
+///     .on_event(|event| println!("{} pressed home", event.controller.name))
+///     .run()
+///     .unwrap();
+/// ```
+pub struct Listener {
+    pub(crate) bindings: Vec<Binding>,
+    /// Actions given in code, by binding index, taking precedence over the configured ones.
+    pub(crate) actions: Vec<Option<Box<dyn Action>>>,
+    pub(crate) registry: Actions,
+    pub(crate) handlers: Handlers,
+    pub(crate) control: Option<PathBuf>,
+    pub(crate) reload: Option<ReloadHandler>,
+    pub(crate) watch: Option<PathBuf>,
+    pub(crate) notify: bool,
+    pub(crate) grab: bool,
+    pub(crate) stop: Option<Arc<EventFd>>,
+}
+
+/// Stops a running [`Listener`] like SIGTERM would, from a handler or another thread.
+#[derive(Clone)]
+pub struct Stopper(Arc<EventFd>);
+
+impl Stopper {
+    pub fn stop(&self) {
+        let _ = self.0.write(1);
+    }
+}
+
+impl Listener {
+    pub fn new(bindings: Vec<Binding>) -> Listener {
+        Listener {
+            actions: bindings.iter().map(|_| None).collect(),
+            bindings,
+            registry: Actions::default(),
+            handlers: Handlers::default(),
+            control: None,
+            reload: None,
+            watch: None,
+            notify: false,
+            grab: false,
+            stop: None,
+        }
+    }
+
+    /// Adds a binding that runs `action`, whatever its `command` or `action` fields say.
+    pub fn bind(mut self, binding: Binding, action: impl Action + 'static) -> Listener {
+        self.bindings.push(binding);
+        self.actions.push(Some(Box::new(action)));
+        self
+    }
+
+    /// Makes `name` available as an `action` type for the bindings, see [`Actions::register`].
+    pub fn register_action(
+        mut self,
+        name: &str,
+        factory: impl Fn(&toml::Table) -> Result<Box<dyn Action>, String> + 'static,
+    ) -> Listener {
+        self.registry.register(name, factory);
+        self
+    }
+
+    /// Calls `handler` for every binding that fires, before its action runs.
+    pub fn on_event(mut self, handler: impl FnMut(&ButtonEvent) + 'static) -> Listener {
+        self.handlers.event.push(Box::new(handler));
+        self
+    }
+
+    /// Calls `handler` with every raw evdev event read from a controller, bound or not.
+    pub fn on_input(mut self, handler: impl FnMut(&Controller, &InputEvent) + 'static) -> Listener {
+        self.handlers.input.push(Box::new(handler));
+        self
+    }
+
+    /// Sends every binding that fires to `sender`.
+    pub fn channel(self, sender: Sender<ButtonEvent>) -> Listener {
+        self.on_event(move |event| {
+            let _ = sender.send(event.clone());
+        })
+    }
+
+    /// Calls `handler` with the name and event of every `emit` action.
+    pub fn on_emit(mut self, handler: impl FnMut(&str, &ButtonEvent) + 'static) -> Listener {
+        self.handlers.emit.push(Box::new(handler));
+        self
+    }
+
+    /// Calls `handler` when an action fails, the listener itself keeps running.
+    pub fn on_error(mut self, handler: impl FnMut(&Errors) + 'static) -> Listener {
+        self.handlers.error.push(Box::new(handler));
+        self
+    }
+
+    pub fn on_connect(mut self, handler: impl FnMut(&Controller) + 'static) -> Listener {
+        self.handlers.connect.push(Box::new(handler));
+        self
+    }
+
+    pub fn on_disconnect(mut self, handler: impl FnMut(&Controller) + 'static) -> Listener {
+        self.handlers.disconnect.push(Box::new(handler));
+        self
+    }
+
+    /// Answers [`control`](crate::control) requests on the socket at `path`.
+    ///
+    /// [`Listener::run`] fails if another listener already answers there.
+    pub fn control_socket(mut self, path: impl Into<PathBuf>) -> Listener {
+        self.control = Some(path.into());
+        self
+    }
+
+    /// Lets a `reload` replace the bindings given to [`Listener::new`] with what `reload` returns.
+    ///
+    /// Reloads happen on SIGHUP, on the control socket's `reload` and after changes to the
+    /// [`watch_config`](Listener::watch_config) file. SIGHUP is blocked on the listener's thread,
+    /// other threads have to block it too or it still ends the process.
+    ///
+    /// The bindings added with [`Listener::bind`] are kept, after the reloaded ones. If
+    /// `reload` fails or one of its actions is invalid, the current bindings stay active
+    /// and the error goes to the `on_error` handlers. Open controllers stay open either way.
+    pub fn reload_with(
+        mut self,
+        reload: impl FnMut() -> Result<Vec<Binding>, Errors> + 'static,
+    ) -> Listener {
+        self.reload = Some(Box::new(reload));
+        self
+    }
+
+    /// Reloads whenever the file at `path` is written or replaced, see [`Listener::reload_with`].
+    pub fn watch_config(mut self, path: impl Into<PathBuf>) -> Listener {
+        self.watch = Some(path.into());
+        self
+    }
+
+    /// When started as a `Type=notify` systemd service, sends `READY=1` once the connected
+    /// controllers are open, pings the watchdog if `WatchdogSec=` is set and keeps
+    /// `STATUS=` up to date with the number of controllers.
+    pub fn notify_systemd(mut self) -> Listener {
+        self.notify = true;
+        self
+    }
+
+    /// Grabs every controller, so only the listener gets its events, and passes them on through
+    /// a uinput copy of the controller, minus the buttons that have a binding of their own.
+    ///
+    /// Games then see a normal pad whose home button, say, does nothing. Rumble sent to
+    /// the copy is played on the controller. Controllers that can't be copied, for lack of
+    /// access to `/dev/uinput`, are listened to without grabbing them. Other listeners get
+    /// nothing from a grabbed controller, nor from the copy, which they skip.
+    pub fn grab(mut self) -> Listener {
+        self.grab = true;
+        self
+    }
+
+    /// A handle that makes [`Listener::run`] return.
+    pub fn stopper(&mut self) -> Result<Stopper, Errors> {
+        let stop = match &self.stop {
+            Some(stop) => stop.clone(),
+            None => {
+                let stop = EventFd::from_flags(EfdFlags::EFD_CLOEXEC | EfdFlags::EFD_NONBLOCK)
+                    .map_err(|_| Errors::Epoll)?;
+                self.stop.insert(Arc::new(stop)).clone()
+            }
+        };
+        Ok(Stopper(stop))
+    }
+
+    /// Opens the connected controllers and keeps listening until SIGINT or SIGTERM,
+    /// which are blocked on this thread meanwhile, or a [`Stopper`], then stops the
+    /// `kill_on_exit` commands.
+    ///
+    /// Fails right away if a binding refers to an unknown or misconfigured action.
+    pub fn run(self) -> Result<(), Errors> {
+        EventLoop::new(self)?.run()
+    }
+}
@@ -0,0 +1,122 @@
+//! Where the binary's log goes: stderr, or straight to the journal when systemd
+//! connected stderr to it, keeping the priority and the fields of each record.
+
+use log::{
+    Level, LevelFilter, Log, Metadata, Record,
+    kv::{self, Key, Value, VisitSource},
+};
+use std::{
+    env,
+    fs::File,
+    io,
+    os::{
+        fd::AsFd,
+        unix::{fs::MetadataExt, net::UnixDatagram},
+    },
+};
+
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+struct Logger {
+    level: LevelFilter,
+    /// `None` logs to stderr.
+    journal: Option<UnixDatagram>,
+}
+
+/// Installs the logger, showing records up to `level`.
+pub fn init(level: LevelFilter) {
+    let logger = Logger {
+        level,
+        journal: journal(),
+    };
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+/// The journal socket, if stderr is the stream systemd announced in `$JOURNAL_STREAM`.
+fn journal() -> Option<UnixDatagram> {
+    let stream = env::var("JOURNAL_STREAM").ok()?;
+    let (dev, ino) = stream.split_once(':')?;
+    let stderr = File::from(io::stderr().as_fd().try_clone_to_owned().ok()?)
+        .metadata()
+        .ok()?;
+    if stderr.dev().to_string() != dev || stderr.ino().to_string() != ino {
+        return None;
+    }
+    let socket = UnixDatagram::unbound().ok()?;
+    socket.connect(JOURNAL_SOCKET).ok()?;
+    Some(socket)
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut fields = Fields(Vec::new());
+        let _ = record.key_values().visit(&mut fields);
+
+        if let Some(journal) = &self.journal
+            && journal.send(&journal_entry(record, &fields.0)).is_ok()
+        {
+            return;
+        }
+        let mut line = format!("[{}] {}", record.level(), record.args());
+        for (key, value) in &fields.0 {
+            if value.contains(char::is_whitespace) {
+                line.push_str(&format!(" {key}={value:?}"));
+            } else {
+                line.push_str(&format!(" {key}={value}"));
+            }
+        }
+        eprintln!("{line}");
+    }
+
+    fn flush(&self) {}
+}
+
+/// The key-values of a record, formatted.
+struct Fields(Vec<(String, String)>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.push((key.to_string(), value.to_string()));
+        Ok(())
+    }
+}
+
+/// A record in the journal's native protocol, its fields upper-cased.
+fn journal_entry(record: &Record, fields: &[(String, String)]) -> Vec<u8> {
+    let priority = match record.level() {
+        Level::Error => "3",
+        Level::Warn => "4",
+        Level::Info => "6",
+        Level::Debug | Level::Trace => "7",
+    };
+    let mut entry = Vec::new();
+    journal_field(&mut entry, "PRIORITY", priority);
+    journal_field(&mut entry, "SYSLOG_IDENTIFIER", "guiders");
+    journal_field(&mut entry, "MESSAGE", &record.args().to_string());
+    for (key, value) in fields {
+        journal_field(&mut entry, &key.to_ascii_uppercase(), value);
+    }
+    entry
+}
+
+/// `KEY=value`, or the length-prefixed form for values that span lines.
+fn journal_field(entry: &mut Vec<u8>, key: &str, value: &str) {
+    entry.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
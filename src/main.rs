@@ -1,119 +1,368 @@
-use core::time;
-use std::{env, process::Command, sync::Arc, thread};
-use udev::{Enumerator, MonitorBuilder};
-
-#[derive(Debug)]
-pub enum Errors {
-    UdevSubsystem,
-    UdevDeviceScan,
-    UdevError,
-    UdevMonitor,
-
-    EvdevOpen,
-    EvdevFetch(String),
-
-    NotController,
-    NoDevicePath,
-    InvalidParams,
+mod cli;
+mod logger;
+
+use cli::Command;
+use evdev::{EventSummary, KeyCode};
+use guiders::{
+    Actions, Config, Controller, Errors, Listener,
+    control::{self, Request},
+    device,
+};
+use std::{
+    cell::RefCell,
+    env, fs, io,
+    path::{self, PathBuf},
+    process::ExitCode,
+    rc::Rc,
+};
+
+fn main() -> ExitCode {
+    let cli = match cli::parse(env::args().skip(1).collect()) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::from(2);
+        }
+    };
+    logger::init(cli.verbosity);
+
+    match execute(cli.command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            log::error!("{e}");
+            ExitCode::FAILURE
+        }
+    }
 }
 
-impl std::fmt::Display for Errors {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Errors::UdevSubsystem => write!(f, "Failed to filter devices by input subsystem."),
-            Errors::UdevDeviceScan => write!(f, "Failed to scan for devices."),
-            Errors::UdevError => write!(f, "Failed to udev."),
-            Errors::UdevMonitor => write!(f, "Failed to monitor for new devices."),
-            Errors::EvdevOpen => write!(f, "Failed to open device."),
-            Errors::EvdevFetch(e) => write!(f, "Failed to fetch device events: '{e}'."),
-            Errors::NotController => write!(f, "This device is not a controller."),
-            Errors::NoDevicePath => write!(f, "This device does not have a path? Wtf how?"),
-            Errors::InvalidParams => write!(
-                f,
-                "Invalid parameters. Please provide a command to execute once the home button is pressed."
-            ),
+fn execute(command: Command) -> Result<(), Errors> {
+    match command {
+        Command::Run(path) => {
+            let (config, path) = load(path)?;
+            run(config, Some(path))
+        }
+        Command::Spawn(command) => run(Config::from_command(command), None),
+        Command::List { json } => list(json),
+        Command::Monitor => monitor(),
+        Command::Check(path) => check(path),
+        Command::Learn {
+            append,
+            config,
+            command,
+        } => learn(append, config, command),
+        Command::Ctl(request) => ctl(request),
+        Command::InstallService { force, config } => install_service(force, config),
+        Command::Help => {
+            println!("{}", cli::USAGE);
+            Ok(())
+        }
+        Command::Version => {
+            println!("guiders {}", env!("CARGO_PKG_VERSION"));
+            Ok(())
         }
     }
 }
 
-fn main() -> Result<(), Errors> {
-    let args: Arc<Vec<String>> = Arc::new(env::args().collect::<Vec<String>>()[1..].to_vec());
-    if args.len() == 0 {
-        return Err(Errors::InvalidParams);
-    }
-
-    let mut enumerator = Enumerator::new().map_err(|_| Errors::UdevError)?;
-    enumerator
-        .match_subsystem("input")
-        .map_err(|_| Errors::UdevSubsystem)?;
-    let devices = enumerator
-        .scan_devices()
-        .map_err(|_| Errors::UdevDeviceScan)?;
-    for device in devices {
-        let _ = verify_device(device, &args);
-    }
-
-    let monitor = MonitorBuilder::new()
-        .and_then(|v| v.match_subsystem("input"))
-        .and_then(|v| v.listen())
-        .map_err(|_| Errors::UdevMonitor)?;
-    let mut monitor = monitor.iter();
-    loop {
-        while let Some(event) = monitor.next() {
-            if event.event_type() != udev::EventType::Add {
-                continue;
+/// The config at `path`, or at the default path without one.
+fn load(path: Option<PathBuf>) -> Result<(Config, PathBuf), Errors> {
+    let path = match path {
+        Some(path) => path,
+        None => {
+            let path = Config::default_path().ok_or(Errors::InvalidParams)?;
+            if !path.exists() {
+                return Err(Errors::NoConfig(path.display().to_string()));
             }
-            println!("{} CONNECTED", event.sysname().to_str().unwrap());
-            let _ = verify_device(event.device(), &args);
+            path
+        }
+    };
+    Ok((Config::load(&path)?, path))
+}
+
+/// The daemon, reloading from `path` if the config came from a file.
+fn run(config: Config, path: Option<PathBuf>) -> Result<(), Errors> {
+    let mut listener = Listener::new(config.bindings).notify_systemd();
+    if config.grab {
+        listener = listener.grab();
+    }
+    if let Some(socket) = control::default_path() {
+        listener = listener.control_socket(socket);
+    }
+    if let Some(path) = path {
+        if config.watch {
+            listener = listener.watch_config(&path);
         }
-        thread::sleep(time::Duration::from_secs(1));
+        listener = listener.reload_with(move || Config::load(&path).map(|c| c.bindings));
     }
+    listener.run()
 }
 
-fn verify_device(device: udev::Device, args: &Arc<Vec<String>>) -> Result<(), Errors> {
-    device
-        .properties()
-        .find(|v| v.name() == "ID_INPUT_JOYSTICK" && v.value() == "1")
-        .ok_or(Errors::NotController)?;
-    let devnode = device
-        .devnode()
-        .map(|v| v.to_string_lossy().to_string())
-        .ok_or(Errors::NoDevicePath)?;
-    println!("Device found: {}", devnode);
-
-    let args = args.clone();
-    thread::spawn(move || {
-        let _ = listen_for_key(&devnode, args).map_err(|e| eprintln!("{e}"));
-    });
+fn list(json: bool) -> Result<(), Errors> {
+    let details = device::details()?;
+    if json {
+        let json =
+            serde_json::to_string_pretty(&details).map_err(|e| Errors::Action(e.to_string()))?;
+        println!("{json}");
+        return Ok(());
+    }
 
+    if details.is_empty() {
+        println!("No controllers found.");
+    }
+    for (i, details) in details.iter().enumerate() {
+        let controller = &details.controller;
+        if i > 0 {
+            println!();
+        }
+        println!("{}  {}", controller.devnode, controller.name);
+        println!(
+            "  id       {:04x}:{:04x} version {:04x}, {} bus",
+            controller.vendor_id, controller.product_id, controller.version, controller.bus_type
+        );
+        println!("  uniq     {}", or_none(&controller.uniq));
+        println!("  phys     {}", or_none(&controller.phys));
+        println!("  keys     {}", or_none(&details.keys.join(" ")));
+        let axes: Vec<String> = details
+            .axes
+            .iter()
+            .map(|axis| format!("{} {}..{}", axis.name, axis.min, axis.max))
+            .collect();
+        println!("  axes     {}", or_none(&axes.join(", ")));
+        println!("  rumble   {}", or_none(&details.force_feedback.join(" ")));
+        println!("  leds     {}", or_none(&details.leds.join(" ")));
+        let battery = details.power_supply.as_ref().map(|supply| {
+            let mut battery = supply.name.clone();
+            if let Some(capacity) = supply.capacity {
+                battery.push_str(&format!(" {capacity}%"));
+            }
+            if let Some(status) = &supply.status {
+                battery.push_str(&format!(" {status}"));
+            }
+            battery
+        });
+        println!("  battery  {}", or_none(&battery.unwrap_or_default()));
+    }
     Ok(())
 }
 
-fn listen_for_key(device_path: &str, args: Arc<Vec<String>>) -> Result<(), Errors> {
-    let mut device = evdev::Device::open(device_path).map_err(|_| Errors::EvdevOpen)?;
-    let name = &device.name().unwrap_or("Nameless device").to_string();
+fn or_none(value: &str) -> &str {
+    if value.is_empty() { "none" } else { value }
+}
+
+/// Prints every event of every controller until interrupted.
+fn monitor() -> Result<(), Errors> {
+    Listener::new(Vec::new())
+        .on_connect(|controller| println!("{}\tconnected\t{}", controller.devnode, controller.name))
+        .on_disconnect(|controller| println!("{}\tdisconnected", controller.devnode))
+        .on_input(|controller, event| {
+            let line = match event.destructure() {
+                EventSummary::Synchronization(..) => return,
+                EventSummary::Key(_, key, value) => format!("{key:?}\t{value}"),
+                EventSummary::AbsoluteAxis(_, axis, value) => format!("{axis:?}\t{value}"),
+                _ => format!(
+                    "{:?}\t{}\t{}",
+                    event.event_type(),
+                    event.code(),
+                    event.value()
+                ),
+            };
+            println!("{}\t{line}", controller.devnode);
+        })
+        .run()
+}
+
+/// Loads the config and builds every action, without opening any controller.
+fn check(path: Option<PathBuf>) -> Result<(), Errors> {
+    let (config, path) = load(path)?;
+    let actions = Actions::default();
+    for binding in &config.bindings {
+        actions.build(binding)?;
+    }
+    println!(
+        "{} is valid, bindings: {}",
+        path.display(),
+        config.bindings.len()
+    );
+    Ok(())
+}
 
-    loop {
-        let fetch_events = device
-            .fetch_events()
-            .map_err(|_| Errors::EvdevFetch(name.clone()))?;
+/// Waits for the next button press on any controller and shows what it was,
+/// optionally adding a binding for it to the config.
+fn learn(append: bool, path: Option<PathBuf>, command: Vec<String>) -> Result<(), Errors> {
+    println!("Press a button on any controller...");
+    let pressed: Rc<RefCell<Option<(Controller, KeyCode)>>> = Rc::default();
 
-        for event in fetch_events {
-            let key = event.code();
-            if event.event_type().0 != 1 // EventType::KEY
-                || event.value() != 0
-                || (key != 316 && key != 139)
+    let mut listener = Listener::new(Vec::new());
+    let stopper = listener.stopper()?;
+    let learned = pressed.clone();
+    listener
+        .on_input(move |controller, event| {
+            if let EventSummary::Key(_, key, 1) = event.destructure()
+                && learned.borrow().is_none()
             {
-                continue;
+                *learned.borrow_mut() = Some((controller.clone(), key));
+                stopper.stop();
             }
+        })
+        .run()?;
 
-            println!("Pressed: {}", name);
-            let _ = Command::new(&args[0])
-                .args(&args[1..])
-                .spawn()
-                .map_err(|e| eprintln!("Error running command: {e}"));
+    // Interrupted before anything was pressed.
+    let Some((controller, key)) = pressed.take() else {
+        return Ok(());
+    };
+    println!("Button   {key:?} ({})", key.code());
+    println!("Device   {} ({})", controller.name, controller.devnode);
+    println!(
+        "Id       {:04x}:{:04x}, uniq {}",
+        controller.vendor_id,
+        controller.product_id,
+        or_none(&controller.uniq)
+    );
+
+    let command = if command.is_empty() {
+        vec![
+            "notify-send".to_string(),
+            "guiders".to_string(),
+            "{button} on {device}".to_string(),
+        ]
+    } else {
+        command
+    };
+    let mut binding = toml::Table::new();
+    binding.insert("button".to_string(), format!("{key:?}").into());
+    binding.insert("command".to_string(), command.into());
+    let binding = format!(
+        "[[binding]]\n{}",
+        toml::to_string(&binding).unwrap_or_default()
+    );
+    if !append {
+        println!("\n{binding}");
+        return Ok(());
+    }
+
+    let path = match path {
+        Some(path) => path,
+        None => Config::default_path().ok_or(Errors::InvalidParams)?,
+    };
+    let error = |e: io::Error| Errors::WriteFile(path.display().to_string(), e.to_string());
+    let mut contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            return Err(Errors::ConfigRead(
+                path.display().to_string(),
+                e.to_string(),
+            ));
+        }
+    };
+    if !contents.is_empty() {
+        if !contents.ends_with('\n') {
+            contents.push('\n');
         }
+        contents.push('\n');
+    }
+    contents.push_str(&binding);
+    Config::parse(&contents, &path)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(error)?;
+    }
+    fs::write(&path, contents).map_err(error)?;
+    println!("Added a binding on {key:?} to {}", path.display());
+    Ok(())
+}
+
+/// Sends `request` to the running daemon and prints what it answers.
+fn ctl(request: Request) -> Result<(), Errors> {
+    let socket = control::default_path()
+        .ok_or_else(|| Errors::Control("XDG_RUNTIME_DIR is not set.".to_string()))?;
 
-        thread::sleep(time::Duration::from_millis(250));
+    let response = control::request(&socket, &request)?;
+    if let Some(e) = response.error {
+        return Err(Errors::Control(e));
+    }
+    for controller in response.controllers.unwrap_or_default() {
+        println!(
+            "{}\t{:04x}:{:04x}\t{}",
+            controller.devnode, controller.vendor_id, controller.product_id, controller.name
+        );
     }
+    for binding in response.bindings.unwrap_or_default() {
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            binding.index,
+            binding.name.as_deref().unwrap_or("-"),
+            binding.button,
+            binding.kind,
+            binding.action.as_deref().unwrap_or("-")
+        );
+    }
+    if let Some(paused) = response.paused {
+        println!("{}", if paused { "paused" } else { "running" });
+    }
+    Ok(())
+}
+
+/// Writes a systemd user unit that runs this binary as a `Type=notify` service with a watchdog.
+///
+/// Only the daemon gets stopped with the unit, `kill_on_exit` decides which commands go with it.
+fn install_service(force: bool, config: Option<PathBuf>) -> Result<(), Errors> {
+    let mut exec_start = vec![PathBuf::from("run")];
+    if let Some(config) = config {
+        exec_start.push("--config".into());
+        exec_start.push(path::absolute(&config).map_err(|_| Errors::InvalidParams)?);
+    }
+
+    let dir = Config::default_path()
+        .and_then(|config| Some(config.parent()?.parent()?.join("systemd").join("user")))
+        .ok_or(Errors::InvalidParams)?;
+    let unit = dir.join("guiders.service");
+    let error = |e: io::Error| Errors::WriteFile(unit.display().to_string(), e.to_string());
+    if unit.exists() && !force {
+        return Err(Errors::WriteFile(
+            unit.display().to_string(),
+            "it already exists, pass --force to replace it".to_string(),
+        ));
+    }
+    exec_start.insert(0, env::current_exe().map_err(error)?);
+
+    let exec_start: Vec<String> = exec_start
+        .iter()
+        .map(|arg| unit_quote(&arg.display().to_string()))
+        .collect();
+    let contents = format!(
+        "[Unit]
+Description=Controller button bindings
+PartOf=graphical-session.target
+After=graphical-session.target
+
+[Service]
+Type=notify
+ExecStart={}
+ExecReload=kill -HUP $MAINPID
+Restart=on-failure
+WatchdogSec=30
+KillMode=process
+
+[Install]
+WantedBy=graphical-session.target
+",
+        exec_start.join(" ")
+    );
+    fs::create_dir_all(&dir).map_err(error)?;
+    fs::write(&unit, contents).map_err(error)?;
+
+    println!("Wrote {}", unit.display());
+    println!(
+        "Enable it with: systemctl --user daemon-reload && systemctl --user enable --now guiders"
+    );
+    Ok(())
+}
+
+/// Quotes an `ExecStart=` argument, escaping systemd's `%` specifiers and `$` variables.
+fn unit_quote(arg: &str) -> String {
+    let escaped = arg
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    format!("\"{escaped}\"")
 }
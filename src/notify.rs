@@ -0,0 +1,66 @@
+//! The systemd notification protocol, for running as a `Type=notify` service.
+
+use std::{
+    env,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+    process,
+    time::{Duration, Instant},
+};
+
+pub(crate) struct Notifier {
+    socket: UnixDatagram,
+    /// How often to ping the watchdog, half its timeout.
+    watchdog: Option<Duration>,
+    next_ping: Instant,
+}
+
+impl Notifier {
+    /// Connects to `$NOTIFY_SOCKET`, `None` when systemd isn't waiting for notifications.
+    pub fn from_env() -> Option<Notifier> {
+        let path = env::var("NOTIFY_SOCKET").ok()?;
+        let address = match path.strip_prefix('@') {
+            Some(name) => SocketAddr::from_abstract_name(name).ok()?,
+            None => SocketAddr::from_pathname(&path).ok()?,
+        };
+        let socket = UnixDatagram::unbound().ok()?;
+        socket.connect_addr(&address).ok()?;
+
+        let for_us = env::var("WATCHDOG_PID")
+            .map(|pid| pid == process::id().to_string())
+            .unwrap_or(true);
+        let watchdog = env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|usec| usec.parse().ok())
+            .filter(|&usec| usec > 0 && for_us)
+            .map(|usec| Duration::from_micros(usec) / 2);
+
+        Some(Notifier {
+            socket,
+            watchdog,
+            next_ping: Instant::now(),
+        })
+    }
+
+    /// Sends `state`, like `READY=1` or `STATUS=...`.
+    pub fn notify(&self, state: &str) {
+        let _ = self.socket.send(state.as_bytes());
+    }
+
+    /// Pings the watchdog when it's due.
+    pub fn tick(&mut self, now: Instant) {
+        if let Some(interval) = self.watchdog
+            && self.next_ping <= now
+        {
+            self.notify("WATCHDOG=1");
+            self.next_ping = now + interval;
+        }
+    }
+
+    /// When `tick` has to ping the watchdog next.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.watchdog.map(|_| self.next_ping)
+    }
+}
@@ -0,0 +1,81 @@
+use std::{
+    collections::HashMap,
+    os::fd::{AsFd, AsRawFd, RawFd},
+    sync::Arc,
+};
+
+use crate::{controller::Controller, gesture::Gestures, uinput::Passthrough};
+
+/// The controllers that are currently open, keyed by devnode.
+#[derive(Default)]
+pub struct Registry {
+    devices: HashMap<String, Entry>,
+}
+
+pub struct Entry {
+    pub controller: Arc<Controller>,
+    pub device: evdev::Device,
+    pub gestures: Gestures,
+    /// The copy that gets the unconsumed events of a grabbed device.
+    pub passthrough: Option<Passthrough>,
+}
+
+impl Registry {
+    pub fn contains(&self, devnode: &str) -> bool {
+        self.devices.contains_key(devnode)
+    }
+
+    pub fn insert(&mut self, devnode: String, entry: Entry) {
+        self.devices.insert(devnode, entry);
+    }
+
+    /// Forgets a removed device, looked up by devnode or syspath.
+    pub fn remove(&mut self, device: &udev::Device) -> Option<(String, Entry)> {
+        let devnode = device.devnode().map(|v| v.to_string_lossy().to_string());
+        let key = self
+            .devices
+            .iter()
+            .find(|(node, entry)| {
+                Some(*node) == devnode.as_ref() || entry.controller.syspath == device.syspath()
+            })
+            .map(|(node, _)| node.clone())?;
+        self.devices.remove_entry(&key)
+    }
+
+    pub fn remove_devnode(&mut self, devnode: &str) -> Option<Entry> {
+        self.devices.remove(devnode)
+    }
+
+    /// The devnode of the open device behind `fd`.
+    pub fn devnode_of(&self, fd: RawFd) -> Option<String> {
+        self.devices
+            .iter()
+            .find(|(_, entry)| entry.device.as_raw_fd() == fd)
+            .map(|(node, _)| node.clone())
+    }
+
+    /// The devnode of the device whose passthrough copy is behind `fd`.
+    pub fn passthrough_of(&self, fd: RawFd) -> Option<String> {
+        self.devices
+            .iter()
+            .find(|(_, entry)| {
+                entry
+                    .passthrough
+                    .as_ref()
+                    .is_some_and(|passthrough| passthrough.as_fd().as_raw_fd() == fd)
+            })
+            .map(|(node, _)| node.clone())
+    }
+
+    pub fn get_mut(&mut self, devnode: &str) -> Option<&mut Entry> {
+        self.devices.get_mut(devnode)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Entry)> {
+        self.devices.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&String, &mut Entry)> {
+        self.devices.iter_mut()
+    }
+}
@@ -0,0 +1,150 @@
+//! Async versions of the controller discovery and button events, for use inside a tokio runtime.
+//!
+//! [`devices`] yields every controller that is connected or plugged in later, and
+//! [`buttons`] opens one of them to stream its key events.
+
+use evdev::{EventStream, EventType, KeyCode};
+use futures_core::Stream;
+use std::{
+    collections::{HashMap, VecDeque},
+    path::PathBuf,
+    pin::Pin,
+    task::{Context, Poll, ready},
+    time::SystemTime,
+};
+use tokio::io::unix::AsyncFd;
+use udev::MonitorSocket;
+
+use crate::{Errors, device};
+
+/// A controller showing up or going away.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceEvent {
+    Added { devnode: String, syspath: PathBuf },
+    Removed { devnode: String, syspath: PathBuf },
+}
+
+/// Controllers that are already connected, followed by every later add and remove.
+pub struct DeviceStream {
+    monitor: AsyncFd<MonitorSocket>,
+    pending: VecDeque<DeviceEvent>,
+    /// Devnodes reported as added, by syspath, so removals only cover controllers.
+    known: HashMap<PathBuf, String>,
+}
+
+/// Starts watching for controllers, see [`DeviceStream`].
+pub fn devices() -> Result<DeviceStream, Errors> {
+    let monitor = AsyncFd::new(device::monitor()?).map_err(|_| Errors::UdevMonitor)?;
+    let mut stream = DeviceStream {
+        monitor,
+        pending: VecDeque::new(),
+        known: HashMap::new(),
+    };
+    for device in device::scan()? {
+        stream.added(&device);
+    }
+    Ok(stream)
+}
+
+impl DeviceStream {
+    fn added(&mut self, device: &udev::Device) {
+        let Ok(devnode) = device::controller_devnode(device) else {
+            return;
+        };
+        let syspath = device.syspath().to_path_buf();
+        if self
+            .known
+            .insert(syspath.clone(), devnode.clone())
+            .is_none()
+        {
+            self.pending
+                .push_back(DeviceEvent::Added { devnode, syspath });
+        }
+    }
+
+    fn removed(&mut self, device: &udev::Device) {
+        let syspath = device.syspath().to_path_buf();
+        if let Some(devnode) = self.known.remove(&syspath) {
+            self.pending
+                .push_back(DeviceEvent::Removed { devnode, syspath });
+        }
+    }
+}
+
+impl Stream for DeviceStream {
+    type Item = Result<DeviceEvent, Errors>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(event) = this.pending.pop_front() {
+                return Poll::Ready(Some(Ok(event)));
+            }
+
+            let mut guard = match ready!(this.monitor.poll_read_ready(cx)) {
+                Ok(guard) => guard,
+                Err(_) => return Poll::Ready(Some(Err(Errors::UdevMonitor))),
+            };
+            let events: Vec<udev::Event> = guard.get_inner().iter().collect();
+            guard.clear_ready();
+
+            for event in events {
+                match event.event_type() {
+                    udev::EventType::Add => this.added(&event.device()),
+                    udev::EventType::Remove => this.removed(&event.device()),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// A button going down or up, autorepeat is left out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub key: KeyCode,
+    pub pressed: bool,
+    pub time: SystemTime,
+}
+
+/// The key events of one controller, built on evdev's [`EventStream`].
+pub struct ButtonStream {
+    events: EventStream,
+    name: String,
+}
+
+/// Opens `devnode` and streams its buttons until the device goes away.
+pub fn buttons(devnode: &str) -> Result<ButtonStream, Errors> {
+    let device = evdev::Device::open(devnode).map_err(|_| Errors::EvdevOpen)?;
+    let name = device.name().unwrap_or("Nameless device").to_string();
+    let events = device.into_event_stream().map_err(|_| Errors::EvdevOpen)?;
+    Ok(ButtonStream { events, name })
+}
+
+impl ButtonStream {
+    pub fn device(&self) -> &evdev::Device {
+        self.events.device()
+    }
+}
+
+impl Stream for ButtonStream {
+    type Item = Result<KeyEvent, Errors>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            let event = match ready!(this.events.poll_event(cx)) {
+                Ok(event) => event,
+                Err(_) => return Poll::Ready(Some(Err(Errors::EvdevFetch(this.name.clone())))),
+            };
+            if event.event_type() != EventType::KEY || event.value() > 1 {
+                continue;
+            }
+            return Poll::Ready(Some(Ok(KeyEvent {
+                key: KeyCode::new(event.code()),
+                pressed: event.value() == 1,
+                time: event.timestamp(),
+            })));
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use nix::{
+    sys::{
+        epoll::{Epoll, EpollCreateFlags, EpollEvent, EpollFlags},
+        signal::{SigSet, Signal, kill, killpg},
+    },
+    unistd::Pid,
+};
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    os::{
+        fd::{AsFd, BorrowedFd, FromRawFd, OwnedFd},
+        unix::process::CommandExt,
+    },
+    process::{Child, Command},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{Binding, Errors};
+
+/// What a press does while the previous run of the binding's command is still alive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Concurrency {
+    /// Start another copy next to it.
+    #[default]
+    Parallel,
+    /// Do nothing.
+    Ignore,
+    /// Start it once the running one exits.
+    Queue,
+    /// Kill the running one's process group and start again.
+    Restart,
+    /// Stop the running one: SIGTERM to its process group, SIGKILL after `kill_timeout_ms`.
+    Toggle,
+}
+
+/// Keeps the children that actions started, per binding, and reaps them once they exit.
+///
+/// Children of bindings with `kill_on_exit` are stopped by [`Supervisor::shutdown`], the
+/// others keep running after guiders exits.
+pub struct Supervisor {
+    runs: HashMap<usize, Runs>,
+    /// The pidfds of the children, so exits are noticed even when SIGCHLD goes to another thread.
+    exits: Option<Epoll>,
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Supervisor {
+            runs: HashMap::new(),
+            exits: Epoll::new(EpollCreateFlags::EPOLL_CLOEXEC).ok(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Runs {
+    children: Vec<Run>,
+    queue: VecDeque<(Command, bool, Option<Duration>)>,
+}
+
+struct Run {
+    child: Child,
+    /// Whether it leads a process group of its own, which gets the signals then.
+    group: bool,
+    /// Registered in `exits`, which it leaves once closed.
+    _pidfd: Option<OwnedFd>,
+    /// Set once a toggle sent SIGTERM, it gets SIGKILL when it passes.
+    kill_at: Option<Instant>,
+    /// For `kill_on_exit`, how long it gets between SIGTERM and SIGKILL on shutdown.
+    on_exit: Option<Duration>,
+}
+
+impl Supervisor {
+    /// Starts `command` for the binding filed under `id`, as far as its `concurrency` allows.
+    ///
+    /// The listener gives each binding an id that it keeps across reloads, as long as it
+    /// keeps its name, or its button, trigger and action for unnamed ones.
+    pub fn spawn(
+        &mut self,
+        id: usize,
+        binding: &Binding,
+        mut command: Command,
+    ) -> Result<(), Errors> {
+        // Children inherit the signal mask of the thread that spawns them, std's posix_spawn
+        // path included, and the listener's thread blocks SIGTERM, SIGINT, SIGHUP and SIGCHLD
+        // for its signalfd. Toggles and shutdowns would then only stop them with SIGKILL.
+        // SAFETY: pthread_sigmask is async-signal-safe.
+        unsafe {
+            command.pre_exec(|| SigSet::empty().thread_set_mask().map_err(io::Error::from));
+        }
+        // A binding keeps its children across a reload that changes these, so each run
+        // remembers whether it got a group.
+        let group = matches!(
+            binding.concurrency,
+            Concurrency::Restart | Concurrency::Toggle
+        ) || binding.kill_on_exit;
+        if group {
+            // Its own group, so stopping it also stops whatever it started, like the
+            // program behind a wrapper script.
+            command.process_group(0);
+        }
+        let on_exit = binding.kill_on_exit.then(|| binding.kill_timeout());
+
+        let runs = self.runs.entry(id).or_default();
+        runs.reap();
+
+        if !runs.children.is_empty() {
+            match binding.concurrency {
+                Concurrency::Parallel => {}
+                Concurrency::Ignore => return Ok(()),
+                Concurrency::Queue => {
+                    runs.queue.push_back((command, group, on_exit));
+                    return Ok(());
+                }
+                Concurrency::Restart => {
+                    // Reaped once their pidfd or SIGCHLD says they're gone.
+                    for run in &runs.children {
+                        run.signal(Signal::SIGKILL);
+                    }
+                }
+                Concurrency::Toggle => {
+                    let kill_at = Instant::now() + binding.kill_timeout();
+                    for run in runs.children.iter_mut().filter(|r| r.kill_at.is_none()) {
+                        run.signal(Signal::SIGTERM);
+                        run.kill_at = Some(kill_at);
+                    }
+                    return Ok(());
+                }
+            }
+        }
+
+        let run = start(self.exits.as_ref(), &mut command, group, on_exit)
+            .map_err(|e| Errors::Action(format!("Error running command: {e}")))?;
+        runs.children.push(run);
+        Ok(())
+    }
+
+    /// Collects the children that exited and starts whatever was queued behind them.
+    pub fn reap(&mut self) -> Vec<Errors> {
+        let mut errors = Vec::new();
+        for runs in self.runs.values_mut() {
+            runs.reap();
+            if runs.children.is_empty()
+                && let Some((mut command, group, on_exit)) = runs.queue.pop_front()
+            {
+                match start(self.exits.as_ref(), &mut command, group, on_exit) {
+                    Ok(run) => runs.children.push(run),
+                    Err(e) => errors.push(Errors::Action(format!("Error running command: {e}"))),
+                }
+            }
+        }
+        errors
+    }
+
+    /// Readable once one of the children exited, until [`Supervisor::reap`] collected it.
+    pub fn exits(&self) -> Option<BorrowedFd<'_>> {
+        self.exits.as_ref().map(|exits| exits.0.as_fd())
+    }
+
+    /// SIGKILLs the toggled children that outlived their timeout.
+    pub fn tick(&mut self, now: Instant) {
+        for runs in self.runs.values_mut() {
+            for run in &mut runs.children {
+                if run.kill_at.is_some_and(|at| at <= now) {
+                    run.signal(Signal::SIGKILL);
+                    run.kill_at = None;
+                }
+            }
+        }
+    }
+
+    /// Stops the groups of `kill_on_exit` bindings, SIGTERM first and SIGKILL for those
+    /// still alive after their `kill_timeout_ms`. Blocks until they're gone, drops the queues.
+    pub fn shutdown(&mut self) {
+        let now = Instant::now();
+        let mut stopping: Vec<(Run, Instant)> = Vec::new();
+        for runs in self.runs.values_mut() {
+            runs.queue.clear();
+            runs.reap();
+            for run in runs.children.drain(..) {
+                if let Some(timeout) = run.on_exit {
+                    run.signal(Signal::SIGTERM);
+                    stopping.push((run, now + timeout));
+                }
+            }
+        }
+
+        loop {
+            let now = Instant::now();
+            stopping.retain_mut(|(run, kill_at)| {
+                if !matches!(run.child.try_wait(), Ok(None)) {
+                    return false;
+                }
+                if *kill_at <= now {
+                    run.signal(Signal::SIGKILL);
+                    let _ = run.child.wait();
+                    return false;
+                }
+                true
+            });
+            if stopping.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    /// When `tick` has something to do next.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.runs
+            .values()
+            .flat_map(|runs| &runs.children)
+            .filter_map(|run| run.kill_at)
+            .min()
+    }
+}
+
+impl Run {
+    /// Sends `signal` to its group if it has one, otherwise to it alone.
+    fn signal(&self, signal: Signal) {
+        let pid = Pid::from_raw(self.child.id() as i32);
+        let _ = if self.group {
+            killpg(pid, signal)
+        } else {
+            kill(pid, signal)
+        };
+    }
+}
+
+impl Runs {
+    fn reap(&mut self) {
+        self.children
+            .retain_mut(|run| matches!(run.child.try_wait(), Ok(None)));
+    }
+}
+
+/// Spawns `command`, watching for its exit in `exits`.
+fn start(
+    exits: Option<&Epoll>,
+    command: &mut Command,
+    group: bool,
+    on_exit: Option<Duration>,
+) -> io::Result<Run> {
+    let child = command.spawn()?;
+    // Kernels before 5.3 have no pidfds, SIGCHLD has to do there.
+    // SAFETY: pidfd_open returns a new fd or -1.
+    let pidfd = match unsafe { libc::syscall(libc::SYS_pidfd_open, child.id(), 0) } {
+        -1 => None,
+        fd => Some(unsafe { OwnedFd::from_raw_fd(fd as i32) }),
+    };
+    if let (Some(exits), Some(pidfd)) = (exits, &pidfd) {
+        let _ = exits.add(
+            pidfd,
+            EpollEvent::new(EpollFlags::EPOLLIN, child.id() as u64),
+        );
+    }
+    Ok(Run {
+        child,
+        group,
+        _pidfd: pidfd,
+        kill_at: None,
+        on_exit,
+    })
+}
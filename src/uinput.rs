@@ -0,0 +1,203 @@
+//! Virtual devices that guiders creates through uinput, which it never listens to itself.
+
+use evdev::{
+    AttributeSet, EventSummary, FFEffect, FFEffectCode, InputEvent, KeyCode, UInputCode,
+    UinputAbsSetup, uinput::VirtualDevice,
+};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    ffi::{CStr, CString},
+    io,
+    os::fd::{AsFd, BorrowedFd},
+    rc::Rc,
+};
+
+/// Start of the `phys` of every device created here, see [`is_virtual`].
+const PHYS_PREFIX: &str = "guiders/";
+const KEYBOARD_PHYS: &CStr = c"guiders/keyboard";
+const KEYBOARD_NAME: &str = "guiders virtual keyboard";
+
+/// Whether the input device with this `phys` attribute is one of ours.
+pub(crate) fn is_virtual(phys: &str) -> bool {
+    phys.starts_with(PHYS_PREFIX)
+}
+
+/// The keyboard that the `keys` actions type on. All of them share it, building one only
+/// marks it as wanted, the listener creates it once it starts.
+#[derive(Clone, Default)]
+pub(crate) struct Keyboard(Rc<RefCell<KeyboardState>>);
+
+#[derive(Default)]
+struct KeyboardState {
+    wanted: bool,
+    device: Option<VirtualDevice>,
+}
+
+impl Keyboard {
+    /// Marks the device as needed by some action.
+    pub fn want(&self) {
+        self.0.borrow_mut().wanted = true;
+    }
+
+    /// Creates the device if it's wanted and doesn't exist already.
+    pub fn open(&self) -> io::Result<()> {
+        let KeyboardState { wanted, device } = &mut *self.0.borrow_mut();
+        if *wanted && device.is_none() {
+            let keys: AttributeSet<KeyCode> = (1..KeyCode::BTN_TRIGGER_HAPPY1.code())
+                .map(KeyCode::new)
+                .filter(|&key| Keyboard::supports(key))
+                .collect();
+            *device = Some(
+                VirtualDevice::builder()?
+                    .name(KEYBOARD_NAME)
+                    .with_phys(KEYBOARD_PHYS)?
+                    .with_keys(&keys)?
+                    .build()?,
+            );
+        }
+        Ok(())
+    }
+
+    /// Keyboard keys only: gamepad, mouse and joystick buttons would make it look like
+    /// one of those to udev.
+    pub fn supports(key: KeyCode) -> bool {
+        let code = key.code();
+        (1..KeyCode::BTN_0.code()).contains(&code)
+            || (KeyCode::KEY_OK.code()..KeyCode::BTN_TRIGGER_HAPPY1.code()).contains(&code)
+    }
+
+    /// Sends `events`, each in its own report.
+    pub fn emit(&self, events: &[InputEvent]) -> io::Result<()> {
+        let mut state = self.0.borrow_mut();
+        let device = state
+            .device
+            .as_mut()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+        for event in events {
+            device.emit(std::slice::from_ref(event))?;
+        }
+        Ok(())
+    }
+}
+
+/// A copy of a grabbed controller, with the same name, ids, buttons and axis ranges, that
+/// gets every event of the controller that guiders doesn't consume.
+///
+/// Rumble effects that games upload to the copy are uploaded to the controller and played there.
+pub(crate) struct Passthrough {
+    device: VirtualDevice,
+    /// The controller's effects, by the id the copy gave them.
+    effects: HashMap<i16, FFEffect>,
+    /// Keys whose press went to the copy and that weren't let go since.
+    pressed: HashSet<KeyCode>,
+}
+
+impl Passthrough {
+    pub fn clone_of(controller: &evdev::Device) -> io::Result<Passthrough> {
+        let name = controller.name().unwrap_or_default();
+        let phys = format!(
+            "{PHYS_PREFIX}{}",
+            controller.physical_path().unwrap_or_default()
+        );
+        let phys = CString::new(phys).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+
+        let mut builder = VirtualDevice::builder()?
+            .name(name)
+            .input_id(controller.input_id())
+            .with_phys(&phys)?
+            .with_properties(controller.properties())?;
+        if let Some(keys) = controller.supported_keys() {
+            builder = builder.with_keys(keys)?;
+        }
+        for (axis, info) in controller.get_absinfo()? {
+            builder = builder.with_absolute_axis(&UinputAbsSetup::new(axis, info))?;
+        }
+        if let Some(axes) = controller.supported_relative_axes() {
+            builder = builder.with_relative_axes(axes)?;
+        }
+        if let Some(switches) = controller.supported_switches() {
+            builder = builder.with_switches(switches)?;
+        }
+        if let Some(misc) = controller.misc_properties() {
+            builder = builder.with_msc(misc)?;
+        }
+        if let Some(effects) = controller.supported_ff() {
+            builder = builder
+                .with_ff(effects)?
+                .with_ff_effects_max(controller.max_ff_effects() as u32);
+        }
+
+        Ok(Passthrough {
+            device: builder.build()?,
+            effects: HashMap::new(),
+            pressed: HashSet::new(),
+        })
+    }
+
+    /// Whether a key event goes to the copy: presses unless `consumed`, repeats and
+    /// releases only for keys whose press went, so pausing or reloading while a key is
+    /// held never leaves it stuck nor lets go of one the copy didn't see pressed.
+    pub fn passes_key(&mut self, key: KeyCode, value: i32, consumed: bool) -> bool {
+        match value {
+            1 if consumed => false,
+            1 => {
+                self.pressed.insert(key);
+                true
+            }
+            0 => self.pressed.remove(&key),
+            _ => self.pressed.contains(&key),
+        }
+    }
+
+    /// Sends one report's worth of events.
+    pub fn emit(&mut self, events: &[InputEvent]) -> io::Result<()> {
+        self.device.emit(events)
+    }
+
+    /// Handles what games sent to the copy, which is readable: rumble effects to upload,
+    /// erase or play on the `controller`.
+    pub fn forward_feedback(&mut self, controller: &mut evdev::Device) -> io::Result<()> {
+        let events: Vec<InputEvent> = self.device.fetch_events()?.collect();
+        for event in events {
+            match event.destructure() {
+                EventSummary::UInput(event, UInputCode::UI_FF_UPLOAD, _) => {
+                    let mut upload = self.device.process_ff_upload(event)?;
+                    let id = upload.effect_id();
+                    let uploaded = match self.effects.get_mut(&id) {
+                        Some(effect) => effect.update(upload.effect()),
+                        None => controller.upload_ff_effect(upload.effect()).map(|effect| {
+                            self.effects.insert(id, effect);
+                        }),
+                    };
+                    if let Err(e) = uploaded {
+                        upload.set_retval(-e.raw_os_error().unwrap_or(libc::EIO));
+                    }
+                }
+                EventSummary::UInput(event, UInputCode::UI_FF_ERASE, _) => {
+                    let erase = self.device.process_ff_erase(event)?;
+                    self.effects.remove(&(erase.effect_id() as i16));
+                }
+                EventSummary::ForceFeedback(_, FFEffectCode::FF_GAIN, value) => {
+                    controller.set_ff_gain(value as u16)?
+                }
+                EventSummary::ForceFeedback(_, FFEffectCode::FF_AUTOCENTER, value) => {
+                    controller.set_ff_autocenter(value as u16)?
+                }
+                EventSummary::ForceFeedback(_, code, count) => {
+                    if let Some(effect) = self.effects.get_mut(&(code.0 as i16)) {
+                        effect.play(count)?;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl AsFd for Passthrough {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.device.as_fd()
+    }
+}